@@ -18,6 +18,9 @@ use streamline::CmdQueue;
 use streamline::Colorize;
 use streamline::Contour;
 use streamline::maths::vec2;
+use streamline::maths::Transform;
+use streamline::post::PostEffect;
+use streamline::command_list::CommandList;
 use streamline::tools::loop_with_report;
 
 use streamline_glium_be::GliumBackend;
@@ -40,6 +43,7 @@ fn main() {
     let window = glutin::WindowBuilder::new().with_dimensions(W, H);
     let context = glutin::ContextBuilder::new()
         .with_depth_buffer(16)
+        .with_stencil_buffer(8)
         .with_multisampling(0);
     let mut events_loop = glutin::EventsLoop::new();
     let display = glium::Display::new(window, context, &events_loop).unwrap();
@@ -70,33 +74,56 @@ fn main() {
         (mgr.build().expect("everithing allright"), sp1, sp2, fnt1)
     };
 
+    // darker corners, applied once the frame is drawn
+    be.set_post_effects(&[PostEffect::Vignette {
+                              radius: 0.4,
+                              softness: 0.4,
+                          }])
+        .expect("post effects need glsl 1.40");
+
+    // line grid, just behind the sprites, recorded once
+    //  NO color, therefore should default white
+    let mut grid = CommandList::new();
+    for j in 0..H/10{
+        grid.line(vec2(0, j*10), vec2(W, j*10), 1, 0);
+    }
+    for i in 0..W/10{
+        grid.line(vec2(i*10, 0), vec2(i*10, H), 1, 0);
+    }
+
+    // a small minimap, drawn every frame in its own queue
+    let minimap = be.add_target(W / 4, H / 4).expect("the minimap fits in memory");
+
+    // the window queue keeps its lists from frame to frame
+    let mut reusable = CmdQueue::reusable(&ass_mgr);
+
     #[cfg(feature = "profile")]
     let mut count = 3;
 
     loop_with_report(&mut |_dt: f64| {
         exec_frame(||{
+            // ~~~~~~~~~~ minimap ~~~~~~~~~~~~~~~~
+            let mut m = CmdQueue::new(be.target_surface(&minimap), &ass_mgr);
+            m.clear(&[0.1f32, 0.1, 0.1, 1.0]);
+            m.rect(vec2(W / 8 - 5, H / 8 - 5), vec2(10, 10), 0).with_color(1.0, 0.0, 1.0, 1.0);
+            m.done();
+
             // ~~~~~~~~~~ drawing ~~~~~~~~~~~~~~~~
-            let surface = be.surface(8);
-            let mut q = CmdQueue::new(surface, &ass_mgr);
             {
+                let mut q = reusable.begin_frame(be.surface());
                 q.clear(&[0.4f32, 0.2, 0.1, 1.0]);
 
-                // sprites grid
+                // sprites grid, the logos are too big at their real size
+                q.push_transform(Transform::scale(0.5, 0.5));
                 for i in 0..5{
                     for j in 0..5{
-                        q.sprite(vec2(i * 150, j * 150), 2, sp1);
-                        q.sprite(vec2(i * 155, j * 155), 2, sp2);
+                        q.sprite(vec2(i * 300, j * 300), 2, sp1);
+                        q.sprite(vec2(i * 310, j * 310), 2, sp2);
                     }
                 }
+                q.pop_transform();
 
-                // line grid,  just behind the sprites
-                //  NO color, therefore should default white
-                for j in 0..H/10{
-                    q.line(vec2(0, j*10), vec2(W, j*10), 1, 0);
-                }
-                for i in 0..W/10{
-                    q.line(vec2(i*10, 0), vec2(i*10, H), 1, 0);
-                }
+                q.replay(&mut grid, Transform::identity(), [1.0, 1.0, 1.0, 1.0]);
 
                 // cross lines, should be red and green
                 q.line(vec2(10, 10), vec2(W-10, H-10), 1, 1).with_color(1.0,0.0,0.0,1.0);
@@ -110,6 +137,8 @@ fn main() {
                 q.rect(vec2(W/2 + 200, H/2 + 200), vec2(40, 40), 4).with_color(0.0,1.0,1.0,1.0)
                     .with_border(3).with_color(1.0, 0.0, 0.0, 1.0);
 
+                q.target_sprite(vec2(W - W / 4 - 10, H - H / 4 - 10), 5, &minimap);
+
                 q.text(vec2(0 ,0), 3, fnt1, "Zero");
                 q.text(vec2(10 , 10 ), 3, fnt1, "10 10");
                 q.text(vec2(200, 10 ), 3, fnt1, "200 10");
@@ -118,7 +147,6 @@ fn main() {
                 //q.text(vec2(0, 0), 13, fnt1, "goodbye");
 
             }
-            q.done();
 
         });
 
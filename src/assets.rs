@@ -1,6 +1,10 @@
 use SpriteId;
 use FontId;
+use MaterialId;
+use material::Material;
 use StreamLineBackend;
+use bmfont::BitmapFont;
+use vector_font::VectorFont;
 
 use std::path::PathBuf;
 use image;
@@ -15,6 +19,7 @@ use std::collections::hash_map::DefaultHasher;
 use std::iter::FromIterator;
 
 use std::{fs, io};
+use std::io::Read;
 
 use rect_packer;
 
@@ -65,6 +70,12 @@ pub struct Rect {
 }
 use std::collections::BTreeMap as Map;
 
+/// fonts are either rasterized by the backend or images in the atlas
+enum Font {
+    Vector(u32),
+    Bitmap(BitmapFont, Vec<SpriteId>),
+}
+
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
@@ -76,7 +87,10 @@ pub struct AssetsMgrBuilder<'a, BE>
 {
     be: &'a mut BE,
     sprites_to_include: Vec<PathBuf>,
-    fonts_to_include: Vec<u32>,
+    fonts_to_include: Vec<Font>,
+    font_paths: Vec<PathBuf>,
+    materials: Map<String, MaterialId>,
+    uniforms: Map<MaterialId, Vec<(String, f32)>>,
 }
 
 impl<'a, BE> AssetsMgrBuilder<'a, BE>
@@ -89,6 +103,9 @@ impl<'a, BE> AssetsMgrBuilder<'a, BE>
             be: be,
             sprites_to_include: Vec::new(),
             fonts_to_include: Vec::new(),
+            font_paths: Vec::new(),
+            materials: Map::new(),
+            uniforms: Map::new(),
         }
     }
 
@@ -99,13 +116,44 @@ impl<'a, BE> AssetsMgrBuilder<'a, BE>
         id
     }
 
+    /// adds a TrueType/OpenType font, the file is parsed here and handed to the backend
     pub fn add_font(&mut self, path: &PathBuf) -> Result<FontId, io::Error> {
         let i = self.fonts_to_include.len();
-        let font_file = fs::File::open(path)?;
-        self.fonts_to_include.push(self.be.add_font(font_file));
+        let mut data = Vec::new();
+        fs::File::open(path)?.read_to_end(&mut data)?;
+        let font = self.be.add_font(VectorFont::from_bytes(data)?)?;
+        self.fonts_to_include.push(Font::Vector(font));
+        self.font_paths.push(path.clone());
         Ok(i)
     }
 
+    /// adds a BMFont descriptor (text or binary), its pages are packed with the sprites
+    pub fn add_bitmap_font(&mut self, path: &PathBuf) -> Result<FontId, io::Error> {
+        let i = self.fonts_to_include.len();
+        let mut data = Vec::new();
+        fs::File::open(path)?.read_to_end(&mut data)?;
+        let font = BitmapFont::parse(&data)?;
+
+        // page files are relative to the descriptor
+        let folder = path.parent().map(|p| p.to_path_buf()).unwrap_or_else(PathBuf::new);
+        let pages = font.pages()
+            .iter()
+            .map(|page| self.add_sprite(&folder.join(page)))
+            .collect();
+
+        self.fonts_to_include.push(Font::Bitmap(font, pages));
+        self.font_paths.push(path.clone());
+        Ok(i)
+    }
+
+    /// compiles a material in the backend, it can be found later by its name
+    pub fn add_material(&mut self, material: &Material) -> Result<MaterialId, io::Error> {
+        let id = self.be.add_material(material)?;
+        self.materials.insert(material.name.clone(), id);
+        self.uniforms.insert(id, material.uniforms.clone());
+        Ok(id)
+    }
+
     /// creates the assets manager object, with all the submitted images
     pub fn build(self) -> Result<AssetsMgr, AssetsMgrError> {
 
@@ -161,28 +209,69 @@ impl<'a, BE> AssetsMgrBuilder<'a, BE>
         // load atlas image on backend
         let tex = self.be.add_texture(atlas);
 
-        // now the fonts
+        let manifest = Manifest {
+            sprites: self.sprites_to_include.clone(),
+            fonts: self.font_paths
+                .into_iter()
+                .zip(self.fonts_to_include.iter())
+                .map(|(path, font)| match *font {
+                    Font::Vector(id) => (path, Some(id)),
+                    Font::Bitmap(..) => (path, None),
+                })
+                .collect(),
+            materials: self.materials.iter().map(|(name, id)| (name.clone(), *id)).collect(),
+            atlas: tex,
+        };
+
+        // now the fonts, bitmap ones need to know where their pages ended up
         let font_map = Map::from_iter(self.fonts_to_include
-            .iter()
-            .enumerate()
-            .map(|kv| (kv.0, *kv.1)));
+            .into_iter()
+            .map(|font| match font {
+                Font::Bitmap(mut bitmap, pages) => {
+                    bitmap.locate(dim, |page| {
+                        pages.get(page as usize)
+                            .and_then(|sprite| sprites_loc_map.get(sprite))
+                            .map(|rect| (rect.x, rect.y))
+                    });
+                    Font::Bitmap(bitmap, pages)
+                }
+                font => font,
+            })
+            .enumerate());
 
         Ok(AssetsMgr {
-            _total_size: dim,
+            total_size: dim,
             sprite_locations: sprites_loc_map,
             fonts: font_map,
+            materials: self.materials,
+            uniforms: self.uniforms,
             tex: tex,
+            manifest: manifest,
         })
     }
 }
 
+/// what the assets were made of, with the ids the backend gave them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    /// in the order they were added, pages of bitmap fonts too
+    pub sprites: Vec<PathBuf>,
+    /// bitmap fonts have no id in the backend
+    pub fonts: Vec<(PathBuf, Option<u32>)>,
+    pub materials: Vec<(String, MaterialId)>,
+    pub atlas: u32,
+}
+
 /// Describes every texture previously registered
 /// All meta data is backed up buy the right backend structure
 pub struct AssetsMgr {
-    _total_size: (f32, f32),
+    total_size: (f32, f32),
     sprite_locations: Map<SpriteId, Rect>,
-    fonts: Map<FontId, u32>,
+    fonts: Map<FontId, Font>,
+    materials: Map<String, MaterialId>,
+    uniforms: Map<MaterialId, Vec<(String, f32)>>,
     tex: u32,
+    manifest: Manifest,
 }
 
 impl AssetsMgr {
@@ -200,13 +289,43 @@ impl AssetsMgr {
         }
         None
     }
+    /// size in pixels of the atlas texture, sprite sizes are relative to it
+    pub fn get_atlas_size(&self) -> (f32, f32) {
+        self.total_size
+    }
+    /// a material added with this name
+    pub fn get_material(&self, name: &str) -> Option<MaterialId> {
+        self.materials.get(name).cloned()
+    }
+    /// the uniforms of a material, with the values it was added with
+    pub fn get_uniforms(&self, material: MaterialId) -> &[(String, f32)] {
+        self.uniforms.get(&material).map_or(&[], |u| &u[..])
+    }
     /// returns the atlas texture identifier as regisitered in the backend
     pub fn get_atlas(&self) -> u32 {
         self.tex
     }
 
-    pub fn get_font(&self, id: &FontId) -> u32 {
-        return self.fonts[id];
+    /// the files and names the assets were built from
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// returns the font identifier as registered in the backend, none for
+    /// bitmap fonts: the backend does not know them, see get_bitmap_font
+    pub fn get_font(&self, id: &FontId) -> Option<u32> {
+        match self.fonts.get(id) {
+            Some(&Font::Vector(font)) => Some(font),
+            _ => None,
+        }
+    }
+
+    /// get a font drawn from the atlas, if this is one
+    pub fn get_bitmap_font(&self, id: &FontId) -> Option<&BitmapFont> {
+        match self.fonts.get(id) {
+            Some(&Font::Bitmap(ref font, _)) => Some(font),
+            _ => None,
+        }
     }
 }
 
@@ -216,6 +335,9 @@ mod tests {
     use super::*;
     use super::StreamLineBackend;
     use image::RgbaImage;
+    use vector_font::VectorFont;
+    use RenderTarget;
+    use post::PostEffect;
 
     use std::io;
 
@@ -225,10 +347,26 @@ mod tests {
         fn add_texture(&mut self, _img: RgbaImage) -> u32 {
             0
         }
-        fn add_font<FIO: io::Read>(&mut self, _font: FIO) -> u32 {
-            0
+        fn add_font(&mut self, _font: VectorFont) -> io::Result<u32> {
+            Ok(0)
+        }
+        fn add_material(&mut self, _material: &Material) -> io::Result<MaterialId> {
+            Ok(0)
+        }
+        fn set_post_effects(&mut self, _effects: &[PostEffect]) -> io::Result<()> {
+            Ok(())
+        }
+        fn surface(&mut self) -> Self::Surface {
+            TestBESurface {}
         }
-        fn surface(&mut self, _: u32) -> Self::Surface {
+        fn add_target(&mut self, width: u32, height: u32) -> io::Result<RenderTarget> {
+            Ok(RenderTarget {
+                texture: 0,
+                width: width,
+                height: height,
+            })
+        }
+        fn target_surface(&mut self, _target: &RenderTarget) -> Self::Surface {
             TestBESurface {}
         }
     }
@@ -293,6 +431,38 @@ mod tests {
             builder.build().expect("nothin fancy anymore")
         };
 
-        assert_eq!(mgr.get_font(&0), 0);
+        assert_eq!(mgr.get_font(&0), Some(0));
+        assert_eq!(mgr.get_font(&1), None);
+    }
+
+    #[test]
+    fn load_bitmap_font() {
+
+        use find_folder::Search;
+        use std::path::Path;
+
+        let mut file_location = Search::Parents(3)
+            .for_folder("assets")
+            .expect("some assets folder must exist somewhere");
+        file_location.push(Path::new("pixel-font.fnt"));
+
+        let mut be = TestBE {};
+
+        let (mgr, fnt) = {
+            let mut builder = AssetsMgrBuilder::new(&mut be);
+            let fnt = builder.add_bitmap_font(&file_location).expect("the font must be there");
+            (builder.build().expect("and its page too"), fnt)
+        };
+
+        assert_eq!(mgr.get_font(&fnt), None);
+        let font = mgr.get_bitmap_font(&fnt).expect("this is a bitmap font");
+        assert_eq!(font.base(), 8);
+        assert_eq!(font.kerning('A', 'V'), -1);
+
+        // the page is the only sprite, characters are inside of it
+        let (x, y) = mgr.get_sprite_offset(0).unwrap();
+        let (tx, ty, _, _) = font.get_char('V').unwrap().tex;
+        assert!(tx > x);
+        assert_eq!(ty, y);
     }
 }
@@ -0,0 +1,189 @@
+//! Lists what a frame capture draws and draws any part of it to a png.
+//!
+//!   streamline-inspect frame.slcf [--batch N] [--layer N] [--kind rect] [--png out.png]
+
+extern crate streamline;
+
+use streamline::AssetsMgrBuilder;
+use streamline::Manifest;
+use streamline::StreamLineBackend;
+use streamline::capture::Capture;
+use streamline::capture::Kind;
+use streamline::capture::Bounds;
+use streamline::capture::Primitive;
+use streamline::headless::HeadlessBackend;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::process;
+use std::path::PathBuf;
+use std::collections::BTreeMap as Map;
+use std::collections::BTreeSet as Set;
+
+struct Args {
+    capture: PathBuf,
+    batch: Option<usize>,
+    layer: Option<i32>,
+    kind: Option<Kind>,
+    png: Option<PathBuf>,
+}
+
+fn usage() -> ! {
+    eprintln!("usage: streamline-inspect <capture> [--batch N] [--layer N] \
+               [--kind line|sprite|rect|text] [--png out.png]");
+    process::exit(2);
+}
+
+fn parse_args() -> Args {
+    let mut args = Args {
+        capture: PathBuf::new(),
+        batch: None,
+        layer: None,
+        kind: None,
+        png: None,
+    };
+    let mut capture = None;
+
+    let mut it = env::args().skip(1);
+    while let Some(arg) = it.next() {
+        let mut value = || it.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--batch" => args.batch = Some(value().parse().unwrap_or_else(|_| usage())),
+            "--layer" => args.layer = Some(value().parse().unwrap_or_else(|_| usage())),
+            "--kind" => {
+                args.kind = Some(match value().as_str() {
+                    "line" => Kind::Line,
+                    "sprite" => Kind::Sprite,
+                    "rect" => Kind::Rect,
+                    "text" => Kind::Text,
+                    _ => usage(),
+                })
+            }
+            "--png" => args.png = Some(PathBuf::from(value())),
+            _ if capture.is_none() && !arg.starts_with("--") => capture = Some(PathBuf::from(arg)),
+            _ => usage(),
+        }
+    }
+
+    args.capture = capture.unwrap_or_else(|| usage());
+    args
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+fn list(capture: &Capture, primitives: &[Primitive]) {
+    let mut groups: Map<(usize, i32, Kind), (usize, Bounds)> = Map::new();
+    for p in primitives {
+        let key = (p.batch, p.layer as i32, p.kind);
+        let group = groups.entry(key).or_insert((0, p.bounds));
+        group.0 += 1;
+        group.1 = group.1.union(&p.bounds);
+    }
+
+    let count = groups.keys().map(|k| k.0).collect::<Set<_>>().len();
+    println!("{}x{}, {} calls, {} primitives in {} batches",
+             capture.dimensions.0,
+             capture.dimensions.1,
+             capture.calls.len(),
+             primitives.len(),
+             count);
+
+    let mut last = (None, None);
+    for (&(batch, layer, kind), &(n, b)) in &groups {
+        if last.0 != Some(batch) {
+            println!("batch {}", batch);
+            last = (Some(batch), None);
+        }
+        if last.1 != Some(layer) {
+            println!("  layer {}", layer);
+            last.1 = Some(layer);
+        }
+        println!("    {:<8} {:>6}  [{:.0}, {:.0}] - [{:.0}, {:.0}]",
+                 format!("{:?}", kind).to_lowercase(),
+                 n,
+                 b.left,
+                 b.bottom,
+                 b.right,
+                 b.top);
+    }
+}
+
+/// loads the assets of the capture again, sprites are packed in the same
+/// order so they land in the same place of the atlas
+fn load(be: &mut HeadlessBackend, captured: &Manifest) -> io::Result<Manifest> {
+    let mut fonts = Vec::new();
+    let assets = {
+        let mut builder = AssetsMgrBuilder::new(be);
+        for path in &captured.sprites {
+            builder.add_sprite(path);
+        }
+        // pages of bitmap fonts are in the sprites already
+        for &(ref path, id) in &captured.fonts {
+            let font = match id {
+                Some(_) => builder.add_font(path).map(Some),
+                None => Ok(None),
+            };
+            fonts.push(font.unwrap_or_else(|e| {
+                eprintln!("warning: font {} not loaded: {}", path.display(), e);
+                None
+            }));
+        }
+        builder.build()
+    };
+
+    let assets = assets.or_else(|e| {
+            eprintln!("warning: sprites not loaded ({:?}), they are drawn blank", e);
+            AssetsMgrBuilder::new(be).build()
+        })
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("assets not loaded: {:?}", e)))?;
+
+    // fonts are given with the position they had in the capture
+    let mut ids = Vec::new();
+    let loaded = assets.manifest();
+    let mut next = loaded.fonts.iter();
+    for font in fonts {
+        ids.push(font.and_then(|_| next.next().and_then(|f| f.1)));
+    }
+
+    Ok(Manifest {
+        sprites: captured.sprites.clone(),
+        fonts: captured.fonts.iter().zip(ids).map(|(f, id)| (f.0.clone(), id)).collect(),
+        materials: captured.materials.clone(),
+        atlas: loaded.atlas,
+    })
+}
+
+fn run(args: Args) -> io::Result<()> {
+    let capture = Capture::read(&mut fs::File::open(&args.capture)?)?;
+    let all = capture.primitives();
+
+    let (batch, layer, kind) = (args.batch, args.layer, args.kind);
+    let keep = |p: &Primitive| {
+        batch.map_or(true, |b| b == p.batch) && layer.map_or(true, |l| l == p.layer as i32) &&
+        kind.map_or(true, |k| k == p.kind)
+    };
+    let capture = capture.filter(&keep);
+    // batches keep their numbers from the whole capture
+    let primitives: Vec<Primitive> = all.into_iter().filter(|p| keep(p)).collect();
+    list(&capture, &primitives);
+
+    if let Some(png) = args.png {
+        let (w, h) = capture.dimensions;
+        let mut be = HeadlessBackend::new(w as u32, h as u32);
+        let manifest = load(&mut be, &capture.manifest)?;
+        capture.replay(be.surface(), &manifest);
+        be.frame().save(&png)?;
+        println!("drawn to {}", png.display());
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = parse_args();
+    if let Err(e) = run(args) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,366 @@
+//! BMFont (AngelCode) descriptors, text and binary.
+//! http://www.angelcode.com/products/bmfont/doc/file_format.html
+
+use std::collections::BTreeMap as Map;
+use std::cmp;
+use std::io;
+use std::str;
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// one character of the font, all measures in pixels
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BitmapChar {
+    /// location in the page image
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// where to draw the image relative to the cursor
+    pub xoffset: i32,
+    pub yoffset: i32,
+    /// how much to move the cursor after the character
+    pub xadvance: i32,
+    pub page: u32,
+    /// location in the texture atlas once the pages are packed: x, y(top), w, h
+    pub tex: (f32, f32, f32, f32),
+}
+
+/// a font made of pre-rendered images
+#[derive(Debug, Clone, PartialEq)]
+pub struct BitmapFont {
+    line_height: u32,
+    base: u32,
+    pages: Vec<String>,
+    chars: Map<u32, BitmapChar>,
+    kernings: Map<(u32, u32), i32>,
+}
+
+impl BitmapFont {
+    /// parses a descriptor, the binary format is detected by its "BMF" header
+    pub fn parse(data: &[u8]) -> io::Result<BitmapFont> {
+        if data.starts_with(b"BMF") {
+            parse_binary(data)
+        } else {
+            let text = str::from_utf8(data).map_err(|_| invalid("descriptor is not utf8"))?;
+            parse_text(text)
+        }
+    }
+
+    /// distance in pixels between two lines of text
+    pub fn line_height(&self) -> u32 {
+        self.line_height
+    }
+
+    /// distance in pixels from the top of the line to the base line
+    pub fn base(&self) -> u32 {
+        self.base
+    }
+
+    /// image files of the pages, relative to the descriptor
+    pub fn pages(&self) -> &[String] {
+        &self.pages
+    }
+
+    pub fn get_char(&self, c: char) -> Option<&BitmapChar> {
+        self.chars.get(&(c as u32))
+    }
+
+    /// extra advance to apply between two consecutive characters
+    pub fn kerning(&self, first: char, second: char) -> i32 {
+        *self.kernings.get(&(first as u32, second as u32)).unwrap_or(&0)
+    }
+
+    /// computes the atlas location of every character, given the size of the atlas
+    /// and the offset where each page was placed
+    pub fn locate<F>(&mut self, atlas: (f32, f32), page_offset: F)
+        where F: Fn(u32) -> Option<(f32, f32)>
+    {
+        for c in self.chars.values_mut() {
+            if let Some((x, y)) = page_offset(c.page) {
+                c.tex = (x + c.x as f32 / atlas.0,
+                         y - c.y as f32 / atlas.1,
+                         c.width as f32 / atlas.0,
+                         c.height as f32 / atlas.1);
+            }
+        }
+    }
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn empty_font() -> BitmapFont {
+    BitmapFont {
+        line_height: 0,
+        base: 0,
+        pages: Vec::new(),
+        chars: Map::new(),
+        kernings: Map::new(),
+    }
+}
+
+/// splits a line in its tag and key=value pairs, values may be quoted
+fn tokenize(line: &str) -> (&str, Map<&str, &str>) {
+    let line = line.trim();
+    let (tag, mut rest) = match line.find(' ') {
+        Some(i) => (&line[..i], line[i..].trim_left()),
+        None => (line, ""),
+    };
+
+    let mut pairs = Map::new();
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].trim();
+        rest = &rest[eq + 1..];
+        let value = if rest.starts_with('"') {
+            let end = rest[1..].find('"').map(|i| i + 1).unwrap_or(rest.len());
+            let value = &rest[1..end];
+            rest = &rest[cmp::min(end + 1, rest.len())..];
+            value
+        } else {
+            let end = rest.find(' ').unwrap_or(rest.len());
+            let value = &rest[..end];
+            rest = &rest[end..];
+            value
+        };
+        pairs.insert(key, value);
+        rest = rest.trim_left();
+    }
+    (tag, pairs)
+}
+
+fn number<T: str::FromStr>(pairs: &Map<&str, &str>, key: &str) -> io::Result<T> {
+    pairs.get(key)
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| invalid(key))
+}
+
+fn parse_text(text: &str) -> io::Result<BitmapFont> {
+    let mut font = empty_font();
+
+    for line in text.lines() {
+        let (tag, pairs) = tokenize(line);
+        match tag {
+            "common" => {
+                font.line_height = number(&pairs, "lineHeight")?;
+                font.base = number(&pairs, "base")?;
+            }
+            "page" => {
+                let id: usize = number(&pairs, "id")?;
+                let file = pairs.get("file").ok_or_else(|| invalid("file"))?;
+                if font.pages.len() <= id {
+                    font.pages.resize(id + 1, String::new());
+                }
+                font.pages[id] = file.to_string();
+            }
+            "char" => {
+                let id = number(&pairs, "id")?;
+                font.chars.insert(id,
+                                  BitmapChar {
+                                      x: number(&pairs, "x")?,
+                                      y: number(&pairs, "y")?,
+                                      width: number(&pairs, "width")?,
+                                      height: number(&pairs, "height")?,
+                                      xoffset: number(&pairs, "xoffset")?,
+                                      yoffset: number(&pairs, "yoffset")?,
+                                      xadvance: number(&pairs, "xadvance")?,
+                                      page: number(&pairs, "page")?,
+                                      tex: (0.0, 0.0, 0.0, 0.0),
+                                  });
+            }
+            "kerning" => {
+                let first = number(&pairs, "first")?;
+                let second = number(&pairs, "second")?;
+                font.kernings.insert((first, second), number(&pairs, "amount")?);
+            }
+            // info, chars and kernings counts are not needed
+            _ => {}
+        }
+    }
+
+    Ok(font)
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// little endian reader over a block of the binary format
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.pos + n > self.data.len() {
+            return Err(invalid("unexpected end of descriptor"));
+        }
+        let slice = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+    fn u16(&mut self) -> io::Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(b[0] as u16 | (b[1] as u16) << 8)
+    }
+    fn i16(&mut self) -> io::Result<i16> {
+        Ok(self.u16()? as i16)
+    }
+    fn u32(&mut self) -> io::Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24)
+    }
+}
+
+fn parse_binary(data: &[u8]) -> io::Result<BitmapFont> {
+    let mut font = empty_font();
+
+    let mut reader = Reader {
+        data: data,
+        pos: 3,
+    };
+    if reader.u8()? != 3 {
+        return Err(invalid("only version 3 of the binary format is supported"));
+    }
+
+    while reader.pos < data.len() {
+        let tag = reader.u8()?;
+        let size = reader.u32()? as usize;
+        let mut block = Reader {
+            data: reader.bytes(size)?,
+            pos: 0,
+        };
+
+        match tag {
+            2 => {
+                font.line_height = block.u16()? as u32;
+                font.base = block.u16()? as u32;
+            }
+            3 => {
+                // null terminated file names
+                for name in block.data.split(|b| *b == 0).filter(|n| !n.is_empty()) {
+                    let name = str::from_utf8(name).map_err(|_| invalid("page name is not utf8"))?;
+                    font.pages.push(name.to_string());
+                }
+            }
+            4 => {
+                while block.pos < block.data.len() {
+                    let id = block.u32()?;
+                    font.chars.insert(id,
+                                      BitmapChar {
+                                          x: block.u16()? as u32,
+                                          y: block.u16()? as u32,
+                                          width: block.u16()? as u32,
+                                          height: block.u16()? as u32,
+                                          xoffset: block.i16()? as i32,
+                                          yoffset: block.i16()? as i32,
+                                          xadvance: block.i16()? as i32,
+                                          page: block.u8()? as u32,
+                                          tex: (0.0, 0.0, 0.0, 0.0),
+                                      });
+                    // channel
+                    block.u8()?;
+                }
+            }
+            5 => {
+                while block.pos < block.data.len() {
+                    let first = block.u32()?;
+                    let second = block.u32()?;
+                    font.kernings.insert((first, second), block.i16()? as i32);
+                }
+            }
+            // info block is not needed
+            _ => {}
+        }
+    }
+
+    Ok(font)
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const TEXT: &'static str = "info face=\"Pixel Font\" size=8 bold=0 italic=0 charset=\"\" \
+                                unicode=1 stretchH=100 smooth=0 aa=1 padding=0,0,0,0 spacing=1,1
+common lineHeight=10 base=8 scaleW=64 scaleH=64 pages=1 packed=0
+page id=0 file=\"pixel font_0.png\"
+chars count=2
+char id=65   x=0     y=0     width=5     height=7     xoffset=0     yoffset=1     xadvance=6     page=0  chnl=15
+char id=86   x=6     y=0     width=5     height=7     xoffset=0     yoffset=1     xadvance=6     page=0  chnl=15
+kernings count=1
+kerning first=65  second=86  amount=-1
+";
+
+    #[test]
+    fn text_format() {
+        let font = BitmapFont::parse(TEXT.as_bytes()).expect("valid descriptor");
+
+        assert_eq!(font.line_height(), 10);
+        assert_eq!(font.base(), 8);
+        assert_eq!(font.pages(), &["pixel font_0.png".to_string()]);
+
+        let v = font.get_char('V').expect("V is there");
+        assert_eq!((v.x, v.y, v.width, v.height), (6, 0, 5, 7));
+        assert_eq!((v.xoffset, v.yoffset, v.xadvance), (0, 1, 6));
+        assert!(font.get_char('B').is_none());
+
+        assert_eq!(font.kerning('A', 'V'), -1);
+        assert_eq!(font.kerning('V', 'A'), 0);
+    }
+
+    #[test]
+    fn binary_format() {
+        let mut data = b"BMF\x03".to_vec();
+
+        // common
+        data.extend_from_slice(&[2, 15, 0, 0, 0]);
+        data.extend_from_slice(&[10, 0, 8, 0, 64, 0, 64, 0, 1, 0, 0, 0, 0, 0, 0]);
+        // pages
+        data.extend_from_slice(&[3, 8, 0, 0, 0]);
+        data.extend_from_slice(b"a_0.png\0");
+        // chars
+        data.extend_from_slice(&[4, 20, 0, 0, 0]);
+        data.extend_from_slice(&[65, 0, 0, 0, 1, 0, 2, 0, 5, 0, 7, 0, 0xff, 0xff, 1, 0, 6, 0, 0, 15]);
+        // kernings
+        data.extend_from_slice(&[5, 10, 0, 0, 0]);
+        data.extend_from_slice(&[65, 0, 0, 0, 65, 0, 0, 0, 0xfe, 0xff]);
+
+        let font = BitmapFont::parse(&data).expect("valid descriptor");
+
+        assert_eq!(font.line_height(), 10);
+        assert_eq!(font.base(), 8);
+        assert_eq!(font.pages(), &["a_0.png".to_string()]);
+
+        let a = font.get_char('A').expect("A is there");
+        assert_eq!((a.x, a.y, a.width, a.height), (1, 2, 5, 7));
+        assert_eq!((a.xoffset, a.yoffset, a.xadvance), (-1, 1, 6));
+        assert_eq!(font.kerning('A', 'A'), -2);
+    }
+
+    #[test]
+    fn truncated() {
+        assert!(BitmapFont::parse(b"BMF\x03\x04\x14\x00\x00\x00\x41").is_err());
+        assert!(BitmapFont::parse(b"common lineHeight=a base=8").is_err());
+    }
+
+    #[test]
+    fn locate() {
+        let mut font = BitmapFont::parse(TEXT.as_bytes()).expect("valid descriptor");
+        font.locate((128.0, 128.0), |page| if page == 0 { Some((0.5, 1.0)) } else { None });
+
+        let v = font.get_char('V').unwrap();
+        assert_eq!(v.tex, (0.5 + 6.0 / 128.0, 1.0, 5.0 / 128.0, 7.0 / 128.0));
+    }
+}
@@ -0,0 +1,90 @@
+//! A view over a world bigger than the screen: primitives are issued in world
+//! coordinates and the camera picks what ends up in the surface.
+
+use maths::Vec2;
+use maths::Transform;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Camera {
+    /// world position shown in the middle of the surface
+    pub center: Vec2,
+    /// screen pixels per world unit
+    pub zoom: f32,
+    /// counter clockwise rotation of the world, in radians
+    pub rotation: f32,
+}
+
+impl Camera {
+    pub fn new(center: Vec2) -> Camera {
+        Camera {
+            center: center,
+            zoom: 1.0,
+            rotation: 0.0,
+        }
+    }
+
+    /// moves the center, the offset is given in screen pixels
+    pub fn pan(&mut self, offset: Vec2) {
+        let offset = Transform::rotate(-self.rotation)
+            .apply_vector(Vec2::new(offset.x / self.zoom, offset.y / self.zoom));
+        self.center.x += offset.x;
+        self.center.y += offset.y;
+    }
+
+    /// world to screen pixels, for a surface of the given dimensions
+    pub fn view(&self, dim: (f32, f32)) -> Transform {
+        Transform::translate(dim.0 / 2.0, dim.1 / 2.0)
+            .then(&Transform::scale(self.zoom, self.zoom))
+            .then(&Transform::rotate(self.rotation))
+            .then(&Transform::translate(-self.center.x, -self.center.y))
+    }
+
+    pub fn world_to_screen(&self, p: Vec2, dim: (f32, f32)) -> Vec2 {
+        self.view(dim).apply(p)
+    }
+
+    /// useful for picking, finds what is under a screen position. none
+    /// when the zoom is zero, the whole world is then on a single point
+    pub fn screen_to_world(&self, p: Vec2, dim: (f32, f32)) -> Option<Vec2> {
+        self.view(dim).inverse().map(|t| t.apply(p))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn close(a: Vec2, b: Vec2) -> bool {
+        (a.x - b.x).abs() < 1e-3 && (a.y - b.y).abs() < 1e-3
+    }
+
+    #[test]
+    fn camera() {
+        let dim = (800.0, 600.0);
+        let mut cam = Camera::new(Vec2::new(-1000.0, 50.0));
+
+        // the center of the world is the center of the screen
+        assert!(close(cam.world_to_screen(Vec2::new(-1000.0, 50.0), dim),
+                      Vec2::new(400.0, 300.0)));
+
+        cam.zoom = 2.0;
+        assert!(close(cam.world_to_screen(Vec2::new(-990.0, 50.0), dim),
+                      Vec2::new(420.0, 300.0)));
+
+        cam.rotation = PI / 2.0;
+        assert!(close(cam.world_to_screen(Vec2::new(-990.0, 50.0), dim),
+                      Vec2::new(400.0, 320.0)));
+
+        let p = Vec2::new(13.0, 500.0);
+        assert!(close(cam.world_to_screen(cam.screen_to_world(p, dim).unwrap(), dim), p));
+
+        // with a quarter turn, the right of the screen is down in the world
+        cam.pan(Vec2::new(20.0, 0.0));
+        assert!(close(cam.center, Vec2::new(-1000.0, 40.0)));
+
+        cam.zoom = 0.0;
+        assert!(cam.screen_to_world(p, dim).is_none());
+    }
+}
@@ -0,0 +1,863 @@
+//! Captures of what a queue sent to the backend, saved in a binary format of our
+//! own, to list, filter and replay into any backend loaded with the same assets.
+
+use StreamLineBackendSurface;
+use AssetsMgr;
+use Manifest;
+use MaterialId;
+use Color;
+use BlendMode;
+use Mask;
+use MaskStage;
+use LineLayout;
+use SpriteLayout;
+use RectLayout;
+use TextLayout;
+use layer_depth;
+use resolution::Viewport;
+
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::path::PathBuf;
+use std::mem::transmute;
+
+/// bumped every time the format changes, older captures are not read
+pub const VERSION: u32 = 2;
+
+const MAGIC: &'static [u8; 4] = b"SLCF";
+
+/// one call to the surface
+#[derive(Clone, PartialEq)]
+pub enum Call {
+    Viewport(Option<Viewport>),
+    Scissor(Option<Viewport>),
+    Mask(Option<Mask>),
+    Blend(BlendMode),
+    Material(Option<MaterialId>),
+    Uniform(MaterialId, String, f32),
+    Clear(Color),
+    Sprites(Vec<SpriteLayout>, u32),
+    Lines(Vec<LineLayout>, u32),
+    Rects(Vec<RectLayout>),
+    Texts(Vec<TextLayout>),
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Capture {
+    pub dimensions: (f32, f32),
+    pub manifest: Manifest,
+    pub calls: Vec<Call>,
+}
+
+/// records every call, and passes it to the surface it wraps
+pub struct CaptureSurface<'c, S> {
+    surface: S,
+    capture: &'c mut Capture,
+}
+
+impl Capture {
+    pub fn new(assets: &AssetsMgr) -> Capture {
+        Capture {
+            dimensions: (0.0, 0.0),
+            manifest: assets.manifest().clone(),
+            calls: Vec::new(),
+        }
+    }
+
+    /// a surface to create the queue with, what was captured before is dropped
+    pub fn surface<S>(&mut self, surface: S) -> CaptureSurface<S>
+        where S: StreamLineBackendSurface
+    {
+        self.dimensions = surface.dimensions();
+        self.calls.clear();
+        CaptureSurface {
+            surface: surface,
+            capture: self,
+        }
+    }
+
+    /// issues the calls again. atlas, fonts and materials are looked up in
+    /// the manifest given, usually the one of the assets loaded, other
+    /// textures keep their ids
+    pub fn replay<S>(&self, mut surface: S, to: &Manifest)
+        where S: StreamLineBackendSurface
+    {
+        let texture = |id: u32| if id == self.manifest.atlas { to.atlas } else { id };
+        let font = |id: u32| {
+            self.manifest
+                .fonts
+                .iter()
+                .position(|f| f.1 == Some(id))
+                .and_then(|i| to.fonts.get(i))
+                .and_then(|f| f.1)
+                .unwrap_or(id)
+        };
+        let material = |id: MaterialId| {
+            self.manifest
+                .materials
+                .iter()
+                .find(|m| m.1 == id)
+                .and_then(|m| to.materials.iter().find(|n| n.0 == m.0))
+                .map_or(id, |m| m.1)
+        };
+
+        for call in &self.calls {
+            match *call {
+                Call::Viewport(vp) => surface.set_viewport(vp),
+                Call::Scissor(vp) => surface.set_scissor(vp),
+                Call::Mask(mask) => surface.set_mask(mask),
+                Call::Blend(mode) => surface.set_blend(mode),
+                Call::Material(id) => surface.set_material(id.map(&material)),
+                Call::Uniform(id, ref name, value) => surface.set_uniform(material(id), name, value),
+                Call::Clear(ref color) => surface.clear(color),
+                Call::Sprites(ref sprites, tex) => surface.draw_sprites(sprites, texture(tex)),
+                Call::Lines(ref lines, width) => surface.draw_lines(lines, width),
+                Call::Rects(ref rects) => surface.draw_rects(rects),
+                Call::Texts(ref texts) => {
+                    let texts: Vec<TextLayout> = texts.iter()
+                        .map(|t| TextLayout { font: font(t.font), ..t.clone() })
+                        .collect();
+                    surface.draw_texts(&texts);
+                }
+            }
+        }
+        surface.done();
+    }
+
+    /// every primitive drawn, in the order they reach the backend
+    pub fn primitives(&self) -> Vec<Primitive> {
+        let mut all = Vec::new();
+        self.scan(|_, primitives| all.extend(primitives));
+        all
+    }
+
+    /// a capture with the primitives kept only, the state changes and the
+    /// clears stay as they are. draw calls left empty are dropped. what is
+    /// drawn into a mask is always kept, or nothing inside of it would show
+    pub fn filter<F>(&self, keep: F) -> Capture
+        where F: Fn(&Primitive) -> bool
+    {
+        let mut calls = Vec::new();
+        let mut mask = None;
+        self.scan(|call, primitives| {
+            if let Call::Mask(m) = *call {
+                mask = m;
+            }
+            let shape = match mask {
+                Some(Mask { stage: MaskStage::Write, .. }) |
+                Some(Mask { stage: MaskStage::Erase, .. }) => true,
+                _ => false,
+            };
+            let keep: Vec<bool> = primitives.iter().map(|p| shape || keep(p)).collect();
+            let call = match *call {
+                Call::Sprites(ref sprites, tex) => Call::Sprites(pick(sprites, &keep), tex),
+                Call::Lines(ref lines, width) => Call::Lines(pick(lines, &keep), width),
+                Call::Rects(ref rects) => Call::Rects(pick(rects, &keep)),
+                Call::Texts(ref texts) => Call::Texts(pick(texts, &keep)),
+                ref call => call.clone(),
+            };
+            match call {
+                Call::Sprites(ref v, _) if v.is_empty() => {}
+                Call::Lines(ref v, _) if v.is_empty() => {}
+                Call::Rects(ref v) if v.is_empty() => {}
+                Call::Texts(ref v) if v.is_empty() => {}
+                call => calls.push(call),
+            }
+        });
+
+        Capture {
+            dimensions: self.dimensions,
+            manifest: self.manifest.clone(),
+            calls: calls,
+        }
+    }
+
+    // every call with the primitives it draws, following the viewport to
+    // tell where they are
+    fn scan<F>(&self, mut f: F)
+        where F: FnMut(&Call, Vec<Primitive>)
+    {
+        let whole = Viewport {
+            left: 0,
+            bottom: 0,
+            width: self.dimensions.0 as u32,
+            height: self.dimensions.1 as u32,
+        };
+        let mut viewport = whole;
+        let mut batch = 0;
+
+        for call in &self.calls {
+            let vp = viewport;
+            let pixels = |x: f32, y: f32| {
+                (vp.left as f32 + (x + 1.0) / 2.0 * vp.width as f32,
+                 vp.bottom as f32 + (y + 1.0) / 2.0 * vp.height as f32)
+            };
+            let quad = |l: &[f32]| {
+                Bounds::of(&[pixels(l[1], l[2]),
+                             pixels(l[1] + l[3], l[2] + l[4]),
+                             pixels(l[1] + l[5], l[2] + l[6]),
+                             pixels(l[1] + l[3] + l[5], l[2] + l[4] + l[6])])
+            };
+            let primitive = |layer: f32, kind: Kind, bounds: Bounds| {
+                Primitive {
+                    batch: batch,
+                    layer: layer,
+                    depth: layer_depth(layer),
+                    kind: kind,
+                    bounds: bounds,
+                }
+            };
+
+            let primitives: Vec<Primitive> = match *call {
+                Call::Viewport(v) => {
+                    viewport = v.unwrap_or(whole);
+                    Vec::new()
+                }
+                Call::Sprites(ref sprites, _) => {
+                    sprites.iter().map(|&SpriteLayout(ref l)| primitive(l[0], Kind::Sprite, quad(l))).collect()
+                }
+                Call::Rects(ref rects) => {
+                    rects.iter().map(|&RectLayout(ref l)| primitive(l[0], Kind::Rect, quad(l))).collect()
+                }
+                Call::Lines(ref lines, width) => {
+                    let half = width as f32 / 2.0;
+                    lines.iter()
+                        .map(|&LineLayout(ref l)| {
+                            let b = Bounds::of(&[pixels(l[1], l[2]), pixels(l[3], l[4])]);
+                            let b = Bounds {
+                                left: b.left - half,
+                                bottom: b.bottom - half,
+                                right: b.right + half,
+                                top: b.top + half,
+                            };
+                            primitive(l[0], Kind::Line, b)
+                        })
+                        .collect()
+                }
+                // the size of texts depends on the font, only the position is known
+                Call::Texts(ref texts) => {
+                    texts.iter()
+                        .map(|t| primitive(t.layer, Kind::Text, Bounds::of(&[pixels(t.pos.0, t.pos.1)])))
+                        .collect()
+                }
+                _ => Vec::new(),
+            };
+
+            let draw = match *call {
+                Call::Sprites(..) | Call::Lines(..) | Call::Rects(..) | Call::Texts(..) => true,
+                _ => false,
+            };
+            f(call, primitives);
+            if draw {
+                batch += 1;
+            }
+        }
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut out = Out(Vec::new());
+        out.0.extend_from_slice(MAGIC);
+        out.u32(VERSION);
+        out.f32(self.dimensions.0);
+        out.f32(self.dimensions.1);
+
+        let m = &self.manifest;
+        out.u32(m.atlas);
+        out.u32(m.sprites.len() as u32);
+        for path in &m.sprites {
+            out.path(path);
+        }
+        out.u32(m.fonts.len() as u32);
+        for &(ref path, id) in &m.fonts {
+            out.path(path);
+            out.option(id, |out, id| out.u32(id));
+        }
+        out.u32(m.materials.len() as u32);
+        for &(ref name, id) in &m.materials {
+            out.str(name);
+            out.u32(id);
+        }
+
+        out.u32(self.calls.len() as u32);
+        for call in &self.calls {
+            out.call(call);
+        }
+        w.write_all(&out.0)
+    }
+
+    pub fn read<R: Read>(r: &mut R) -> io::Result<Capture> {
+        let mut data = Vec::new();
+        r.read_to_end(&mut data)?;
+        let mut r = Reader {
+            data: &data,
+            pos: 0,
+        };
+
+        if r.bytes(4)? != MAGIC {
+            return Err(invalid("not a frame capture"));
+        }
+        let version = r.u32()?;
+        if version != VERSION {
+            return Err(invalid(&format!("capture version {}, only {} is supported", version, VERSION)));
+        }
+        let dimensions = (r.f32()?, r.f32()?);
+
+        let atlas = r.u32()?;
+        let mut sprites = Vec::new();
+        for _ in 0..r.u32()? {
+            sprites.push(r.path()?);
+        }
+        let mut fonts = Vec::new();
+        for _ in 0..r.u32()? {
+            let path = r.path()?;
+            fonts.push((path, r.option(|r| r.u32())?));
+        }
+        let mut materials = Vec::new();
+        for _ in 0..r.u32()? {
+            let name = r.str()?;
+            materials.push((name, r.u32()?));
+        }
+
+        let mut calls = Vec::new();
+        for _ in 0..r.u32()? {
+            calls.push(r.call()?);
+        }
+        if r.pos != data.len() {
+            return Err(invalid("trailing bytes after the calls"));
+        }
+
+        Ok(Capture {
+            dimensions: dimensions,
+            manifest: Manifest {
+                sprites: sprites,
+                fonts: fonts,
+                materials: materials,
+                atlas: atlas,
+            },
+            calls: calls,
+        })
+    }
+}
+
+fn pick<T: Clone>(v: &[T], keep: &[bool]) -> Vec<T> {
+    v.iter().zip(keep).filter(|p| *p.1).map(|p| p.0.clone()).collect()
+}
+
+/// what a primitive is
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    Line,
+    Sprite,
+    Rect,
+    Text,
+}
+
+/// area covered on the surface, in pixels from its bottom left corner
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Bounds {
+    pub left: f32,
+    pub bottom: f32,
+    pub right: f32,
+    pub top: f32,
+}
+
+impl Bounds {
+    fn of(points: &[(f32, f32)]) -> Bounds {
+        let mut b = Bounds {
+            left: points[0].0,
+            bottom: points[0].1,
+            right: points[0].0,
+            top: points[0].1,
+        };
+        for p in &points[1..] {
+            b = b.union(&Bounds {
+                left: p.0,
+                bottom: p.1,
+                right: p.0,
+                top: p.1,
+            });
+        }
+        b
+    }
+
+    /// the area covering both
+    pub fn union(&self, other: &Bounds) -> Bounds {
+        Bounds {
+            left: self.left.min(other.left),
+            bottom: self.bottom.min(other.bottom),
+            right: self.right.max(other.right),
+            top: self.top.max(other.top),
+        }
+    }
+}
+
+/// one primitive of a capture
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Primitive {
+    /// the draw call it was in, counted from zero
+    pub batch: usize,
+    /// as given to the queue
+    pub layer: f32,
+    /// as drawn, between 0 and 1, lower is nearer
+    pub depth: f32,
+    pub kind: Kind,
+    pub bounds: Bounds,
+}
+
+impl<'c, S> StreamLineBackendSurface for CaptureSurface<'c, S>
+    where S: StreamLineBackendSurface
+{
+    fn dimensions(&self) -> (f32, f32) {
+        self.surface.dimensions()
+    }
+    fn set_viewport(&mut self, viewport: Option<Viewport>) {
+        self.capture.calls.push(Call::Viewport(viewport));
+        self.surface.set_viewport(viewport);
+    }
+    fn set_scissor(&mut self, scissor: Option<Viewport>) {
+        self.capture.calls.push(Call::Scissor(scissor));
+        self.surface.set_scissor(scissor);
+    }
+    fn set_mask(&mut self, mask: Option<Mask>) {
+        self.capture.calls.push(Call::Mask(mask));
+        self.surface.set_mask(mask);
+    }
+    fn set_blend(&mut self, mode: BlendMode) {
+        self.capture.calls.push(Call::Blend(mode));
+        self.surface.set_blend(mode);
+    }
+    fn set_material(&mut self, material: Option<MaterialId>) {
+        self.capture.calls.push(Call::Material(material));
+        self.surface.set_material(material);
+    }
+    fn set_uniform(&mut self, material: MaterialId, name: &str, value: f32) {
+        self.capture.calls.push(Call::Uniform(material, name.to_string(), value));
+        self.surface.set_uniform(material, name, value);
+    }
+    fn clear(&mut self, color: &Color) {
+        self.capture.calls.push(Call::Clear(*color));
+        self.surface.clear(color);
+    }
+    fn draw_sprites(&mut self, sprites: &[SpriteLayout], tex: u32) {
+        self.capture.calls.push(Call::Sprites(sprites.to_vec(), tex));
+        self.surface.draw_sprites(sprites, tex);
+    }
+    fn draw_lines(&mut self, lines: &[LineLayout], width: u32) {
+        self.capture.calls.push(Call::Lines(lines.to_vec(), width));
+        self.surface.draw_lines(lines, width);
+    }
+    fn draw_rects(&mut self, rects: &[RectLayout]) {
+        self.capture.calls.push(Call::Rects(rects.to_vec()));
+        self.surface.draw_rects(rects);
+    }
+    fn draw_texts(&mut self, texts: &[TextLayout]) {
+        self.capture.calls.push(Call::Texts(texts.to_vec()));
+        self.surface.draw_texts(texts);
+    }
+    fn done(self) {
+        self.surface.done();
+    }
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+// everything little endian, strings and lists after their length
+struct Out(Vec<u8>);
+
+impl Out {
+    fn u8(&mut self, v: u8) {
+        self.0.push(v);
+    }
+    fn u32(&mut self, v: u32) {
+        self.0.extend_from_slice(&[v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8]);
+    }
+    fn f32(&mut self, v: f32) {
+        self.u32(unsafe { transmute::<f32, u32>(v) });
+    }
+    fn floats(&mut self, v: &[f32]) {
+        for f in v {
+            self.f32(*f);
+        }
+    }
+    fn str(&mut self, v: &str) {
+        self.u32(v.len() as u32);
+        self.0.extend_from_slice(v.as_bytes());
+    }
+    fn path(&mut self, v: &PathBuf) {
+        self.str(&v.to_string_lossy());
+    }
+    fn option<T, F: Fn(&mut Out, T)>(&mut self, v: Option<T>, f: F) {
+        match v {
+            Some(v) => {
+                self.u8(1);
+                f(self, v);
+            }
+            None => self.u8(0),
+        }
+    }
+    fn viewport(&mut self, vp: Viewport) {
+        self.u32(vp.left);
+        self.u32(vp.bottom);
+        self.u32(vp.width);
+        self.u32(vp.height);
+    }
+
+    fn call(&mut self, call: &Call) {
+        match *call {
+            Call::Viewport(vp) => {
+                self.u8(0);
+                self.option(vp, Out::viewport);
+            }
+            Call::Scissor(vp) => {
+                self.u8(1);
+                self.option(vp, Out::viewport);
+            }
+            Call::Mask(mask) => {
+                self.u8(2);
+                self.option(mask, |out, mask| {
+                    out.u32(mask.id);
+                    out.u32(mask.depth);
+                    out.u8(match mask.stage {
+                        MaskStage::Write => 0,
+                        MaskStage::Draw => 1,
+                        MaskStage::Erase => 2,
+                    });
+                });
+            }
+            Call::Blend(mode) => {
+                self.u8(3);
+                self.u8(match mode {
+                    BlendMode::Alpha => 0,
+                    BlendMode::Additive => 1,
+                    BlendMode::Multiply => 2,
+                    BlendMode::Screen => 3,
+                    BlendMode::Premultiplied => 4,
+                });
+            }
+            Call::Material(material) => {
+                self.u8(4);
+                self.option(material, Out::u32);
+            }
+            Call::Uniform(material, ref name, value) => {
+                self.u8(5);
+                self.u32(material);
+                self.str(name);
+                self.f32(value);
+            }
+            Call::Clear(ref color) => {
+                self.u8(6);
+                self.floats(color);
+            }
+            Call::Sprites(ref sprites, tex) => {
+                self.u8(7);
+                self.u32(tex);
+                self.u32(sprites.len() as u32);
+                for &SpriteLayout(ref l) in sprites {
+                    self.floats(l);
+                }
+            }
+            Call::Lines(ref lines, width) => {
+                self.u8(8);
+                self.u32(width);
+                self.u32(lines.len() as u32);
+                for &LineLayout(ref l) in lines {
+                    self.floats(l);
+                }
+            }
+            Call::Rects(ref rects) => {
+                self.u8(9);
+                self.u32(rects.len() as u32);
+                for &RectLayout(ref l) in rects {
+                    self.floats(l);
+                }
+            }
+            Call::Texts(ref texts) => {
+                self.u8(10);
+                self.u32(texts.len() as u32);
+                for t in texts {
+                    self.f32(t.layer);
+                    self.floats(&[t.pos.0, t.pos.1]);
+                    self.floats(&[t.color.0, t.color.1, t.color.2, t.color.3]);
+                    self.u32(t.font);
+                    self.str(&t.text);
+                }
+            }
+        }
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.pos + n > self.data.len() {
+            return Err(invalid("unexpected end of capture"));
+        }
+        let slice = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+    fn u32(&mut self) -> io::Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24)
+    }
+    fn f32(&mut self) -> io::Result<f32> {
+        Ok(unsafe { transmute::<u32, f32>(self.u32()?) })
+    }
+    fn floats(&mut self, v: &mut [f32]) -> io::Result<()> {
+        for f in v.iter_mut() {
+            *f = self.f32()?;
+        }
+        Ok(())
+    }
+    fn str(&mut self) -> io::Result<String> {
+        let n = self.u32()? as usize;
+        let bytes = self.bytes(n)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid("string is not utf8"))
+    }
+    fn path(&mut self) -> io::Result<PathBuf> {
+        Ok(PathBuf::from(self.str()?))
+    }
+    fn option<T, F: Fn(&mut Reader<'a>) -> io::Result<T>>(&mut self, f: F) -> io::Result<Option<T>> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(f(self)?)),
+            _ => Err(invalid("bad option")),
+        }
+    }
+    fn viewport(&mut self) -> io::Result<Viewport> {
+        Ok(Viewport {
+            left: self.u32()?,
+            bottom: self.u32()?,
+            width: self.u32()?,
+            height: self.u32()?,
+        })
+    }
+
+    fn call(&mut self) -> io::Result<Call> {
+        let call = match self.u8()? {
+            0 => Call::Viewport(self.option(Reader::viewport)?),
+            1 => Call::Scissor(self.option(Reader::viewport)?),
+            2 => {
+                Call::Mask(self.option(|r| {
+                    Ok(Mask {
+                        id: r.u32()?,
+                        depth: r.u32()?,
+                        stage: match r.u8()? {
+                            0 => MaskStage::Write,
+                            1 => MaskStage::Draw,
+                            2 => MaskStage::Erase,
+                            _ => return Err(invalid("bad mask stage")),
+                        },
+                    })
+                })?)
+            }
+            3 => {
+                Call::Blend(match self.u8()? {
+                    0 => BlendMode::Alpha,
+                    1 => BlendMode::Additive,
+                    2 => BlendMode::Multiply,
+                    3 => BlendMode::Screen,
+                    4 => BlendMode::Premultiplied,
+                    _ => return Err(invalid("bad blend mode")),
+                })
+            }
+            4 => Call::Material(self.option(Reader::u32)?),
+            5 => {
+                let material = self.u32()?;
+                let name = self.str()?;
+                Call::Uniform(material, name, self.f32()?)
+            }
+            6 => {
+                let mut color = [0.0; 4];
+                self.floats(&mut color)?;
+                Call::Clear(color)
+            }
+            7 => {
+                let tex = self.u32()?;
+                let mut sprites = Vec::new();
+                for _ in 0..self.u32()? {
+                    let mut l = [0.0; 15];
+                    self.floats(&mut l)?;
+                    sprites.push(SpriteLayout(l));
+                }
+                Call::Sprites(sprites, tex)
+            }
+            8 => {
+                let width = self.u32()?;
+                let mut lines = Vec::new();
+                for _ in 0..self.u32()? {
+                    let mut l = [0.0; 9];
+                    self.floats(&mut l)?;
+                    lines.push(LineLayout(l));
+                }
+                Call::Lines(lines, width)
+            }
+            9 => {
+                let mut rects = Vec::new();
+                for _ in 0..self.u32()? {
+                    let mut l = [0.0; 11];
+                    self.floats(&mut l)?;
+                    rects.push(RectLayout(l));
+                }
+                Call::Rects(rects)
+            }
+            10 => {
+                let mut texts = Vec::new();
+                for _ in 0..self.u32()? {
+                    let layer = self.f32()?;
+                    let mut v = [0.0; 6];
+                    self.floats(&mut v)?;
+                    texts.push(TextLayout {
+                        layer: layer,
+                        pos: (v[0], v[1]),
+                        color: (v[2], v[3], v[4], v[5]),
+                        font: self.u32()?,
+                        text: self.str()?,
+                    });
+                }
+                Call::Texts(texts)
+            }
+            _ => return Err(invalid("unknown call")),
+        };
+        Ok(call)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use CmdQueue;
+    use AssetsMgrBuilder;
+    use StreamLineBackend;
+    use Blending;
+    use Colorize;
+    use maths::vec2;
+    use tests::TestBE;
+
+    #[test]
+    fn save_and_replay() {
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+
+        let mut capture = Capture::new(&ass);
+        {
+            let mut q = CmdQueue::new(capture.surface(be.surface()), &ass);
+            q.clear(&[0.1, 0.2, 0.3, 1.0]);
+            q.rect(vec2(0, 0), vec2(10, 10), 2).with_color(1.0, 0.0, 0.0, 1.0);
+            q.push_clip(vec2(0, 0), vec2(50, 50));
+            q.line(vec2(0, 0), vec2(10, 10), 3, 1).with_blend(BlendMode::Additive);
+            q.pop_clip();
+            q.begin_mask();
+            q.rect(vec2(0, 0), vec2(20, 20), 0);
+            q.end_mask();
+            q.rect(vec2(5, 5), vec2(20, 20), 0);
+            q.pop_mask();
+            q.done();
+        }
+        assert_eq!(capture.dimensions, (800.0, 600.0));
+        assert!(capture.calls[0] == Call::Clear([0.1, 0.2, 0.3, 1.0]));
+        assert!(capture.calls.iter().any(|c| *c == Call::Blend(BlendMode::Additive)));
+
+        let mut data = Vec::new();
+        capture.write(&mut data).expect("written");
+        let loaded = Capture::read(&mut &data[..]).expect("read");
+        assert!(loaded == capture);
+
+        // replayed, the backend sees the same calls
+        let mut again = Capture::new(&ass);
+        loaded.replay(again.surface(be.surface()), ass.manifest());
+        assert!(again == capture);
+    }
+
+    #[test]
+    fn inspect() {
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+
+        let mut capture = Capture::new(&ass);
+        {
+            let mut q = CmdQueue::new(capture.surface(be.surface()), &ass);
+            q.clear(&[0.0, 0.0, 0.0, 1.0]);
+            q.rect(vec2(10, 20), vec2(30, 40), 1);
+            q.rect(vec2(0, 0), vec2(10, 10), 1);
+            q.line(vec2(0, 0), vec2(100, 0), 2, 7).with_blend(BlendMode::Additive);
+            q.done();
+        }
+
+        let primitives = capture.primitives();
+        assert_eq!(primitives.len(), 3);
+        let pixels = |b: Bounds| (b.left.round(), b.bottom.round(), b.right.round(), b.top.round());
+        let rect = primitives[0];
+        assert_eq!(rect.kind, Kind::Rect);
+        assert_eq!(pixels(rect.bounds), (10.0, 20.0, 40.0, 60.0));
+        assert_eq!(rect.batch, primitives[1].batch);
+        assert_eq!((rect.layer, primitives[1].layer), (1.0, 1.0));
+        assert_eq!(rect.depth, primitives[1].depth);
+        let line = primitives[2];
+        assert_eq!(line.kind, Kind::Line);
+        assert_eq!(pixels(line.bounds), (-1.0, -1.0, 101.0, 1.0));
+        assert_eq!(line.layer, 7.0);
+        assert!(line.depth < rect.depth);
+        assert!(line.batch != rect.batch);
+
+        // the line only, after the clear and the blend mode it needs
+        let lines = capture.filter(|p| p.kind == Kind::Line);
+        assert_eq!(lines.primitives().len(), 1);
+        assert!(lines.calls[0] == Call::Clear([0.0, 0.0, 0.0, 1.0]));
+        assert!(lines.calls.iter().any(|c| *c == Call::Blend(BlendMode::Additive)));
+        assert!(!lines.calls.iter().any(|c| match *c {
+            Call::Rects(..) => true,
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn filter_masked() {
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+
+        let mut capture = Capture::new(&ass);
+        {
+            let mut q = CmdQueue::new(capture.surface(be.surface()), &ass);
+            q.begin_mask();
+            q.rect(vec2(0, 0), vec2(50, 50), 0);
+            q.end_mask();
+            q.rect(vec2(10, 10), vec2(100, 100), 1);
+            q.line(vec2(0, 0), vec2(100, 0), 2, 1);
+            q.pop_mask();
+            q.done();
+        }
+
+        // the mask is written and erased again even when its shape is not picked
+        let picked = capture.filter(|p| p.kind == Kind::Line);
+        let kinds: Vec<Kind> = picked.primitives().iter().map(|p| p.kind).collect();
+        assert_eq!(kinds, vec![Kind::Rect, Kind::Line, Kind::Rect]);
+    }
+
+    #[test]
+    fn versions() {
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+        let mut data = Vec::new();
+        Capture::new(&ass).write(&mut data).expect("written");
+
+        data[4] = VERSION as u8 + 1;
+        let err = Capture::read(&mut &data[..]).err().expect("newer version");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(Capture::read(&mut &b"PNG"[..]).is_err());
+    }
+}
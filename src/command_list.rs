@@ -0,0 +1,333 @@
+//! Display lists: primitives recorded once and replayed into the queue of
+//! every frame, copying the last layouts while nothing they depend on changed.
+
+use CmdQueue;
+use StreamLineBackendSurface;
+use SpriteId;
+use FontId;
+use Color;
+use Colorize;
+use LineLayout;
+use SpriteLayout;
+use RectLayout;
+use TextLayout;
+use Layouts;
+use maths::Vec2;
+use maths::Transform;
+use maths::Origin;
+
+use std::collections::BTreeMap as Map;
+
+/// a primitive as recorded by lists and frames, with the color it was tuned to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Line {
+        src: Vec2,
+        dst: Vec2,
+        width: u32,
+        layer: i32,
+        color: Color,
+    },
+    Sprite {
+        pos: Vec2,
+        layer: i32,
+        sprite: SpriteId,
+    },
+    Rect {
+        pos: Vec2,
+        size: Vec2,
+        layer: i32,
+        color: Color,
+    },
+    Text {
+        pos: Vec2,
+        layer: i32,
+        font: FontId,
+        text: String,
+    },
+}
+
+// everything the layouts depend on
+#[derive(Copy, Clone, PartialEq)]
+struct Key {
+    transform: Transform,
+    tint: Color,
+    dimensions: (f32, f32),
+    origin: Origin,
+    pixel_perfect: bool,
+    line_scale: f32,
+}
+
+// layouts of the last replay, with their sequence from the first one
+struct Generated {
+    key: Key,
+    lines: Vec<(u32, Vec<(LineLayout, u32)>)>,
+    sprites: Vec<(SpriteLayout, u32)>,
+    rects: Vec<(RectLayout, u32)>,
+    texts: Vec<(TextLayout, u32)>,
+    count: u32,
+}
+
+pub struct CommandList {
+    commands: Vec<Command>,
+    generated: Option<Generated>,
+}
+
+/// colors the last primitive recorded, like the tunes of the queue
+pub struct ListTune<'a> {
+    command: &'a mut Command,
+}
+
+impl<'a> Colorize for ListTune<'a> {
+    fn with_color(self, r: f32, g: f32, b: f32, a: f32) -> Self {
+        match *self.command {
+            Command::Line { ref mut color, .. } |
+            Command::Rect { ref mut color, .. } => *color = [r, g, b, a],
+            _ => {}
+        }
+        self
+    }
+}
+
+impl CommandList {
+    pub fn new() -> CommandList {
+        CommandList {
+            commands: Vec::new(),
+            generated: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    pub fn line(&mut self, src: Vec2, dst: Vec2, width: u32, layer: i32) -> ListTune {
+        self.push(Command::Line {
+            src: src,
+            dst: dst,
+            width: width,
+            layer: layer,
+            color: [1.0, 1.0, 1.0, 1.0],
+        })
+    }
+
+    pub fn sprite(&mut self, pos: Vec2, layer: i32, sprite: SpriteId) {
+        self.push(Command::Sprite {
+            pos: pos,
+            layer: layer,
+            sprite: sprite,
+        });
+    }
+
+    pub fn rect(&mut self, pos: Vec2, size: Vec2, layer: i32) -> ListTune {
+        self.push(Command::Rect {
+            pos: pos,
+            size: size,
+            layer: layer,
+            color: [0.0, 0.0, 0.0, 1.0],
+        })
+    }
+
+    pub fn text(&mut self, pos: Vec2, layer: i32, font: FontId, txt: &str) {
+        self.push(Command::Text {
+            pos: pos,
+            layer: layer,
+            font: font,
+            text: txt.to_string(),
+        });
+    }
+
+    fn push(&mut self, command: Command) -> ListTune {
+        self.generated = None;
+        self.commands.push(command);
+        ListTune { command: self.commands.last_mut().unwrap() }
+    }
+}
+
+// the layouts issued from start on, sequence relative to base
+fn since<T: Clone>(list: &[T], order: &[u32], start: usize, base: u32) -> Vec<(T, u32)> {
+    list[start..].iter().cloned().zip(order[start..].iter().map(|seq| seq - base)).collect()
+}
+
+impl<'a, S> CmdQueue<'a, S>
+    where S: StreamLineBackendSurface
+{
+    /// issues the primitives of the list, moved by the transform and with
+    /// their colors multiplied by the tint
+    pub fn replay(&mut self, list: &mut CommandList, t: Transform, tint: Color) {
+
+        let key = Key {
+            transform: self.transform().then(&t),
+            tint: tint,
+            dimensions: self.dimensions(),
+            origin: self.origin,
+            pixel_perfect: self.resolution.map_or(false, |res| res.pixel_perfect),
+            line_scale: self.batches.line_scale,
+        };
+
+        let cached = list.generated.as_ref().map_or(false, |g| g.key == key);
+        if !cached {
+            list.generated = Some(self.generate(&list.commands, t, key));
+            return;
+        }
+
+        let g = list.generated.as_ref().unwrap();
+        let base = self.count;
+        self.count += g.count;
+        let batch = self.batch_mut();
+        for &(width, ref lines) in &g.lines {
+            let list = batch.lines.entry(width).or_insert_with(Layouts::new);
+            for &(layout, seq) in lines {
+                list.push(layout, base + seq);
+            }
+        }
+        for &(layout, seq) in &g.sprites {
+            batch.sprites.push(layout, base + seq);
+        }
+        for &(layout, seq) in &g.rects {
+            batch.rects.push(layout, base + seq);
+        }
+        for &(ref layout, seq) in &g.texts {
+            batch.texts.push(layout.clone(), base + seq);
+        }
+    }
+
+    // issues the commands as usual, and keeps a copy of what they generated
+    fn generate(&mut self, commands: &[Command], t: Transform, key: Key) -> Generated {
+
+        let base = self.count;
+        let lines_before: Map<u32, usize> =
+            self.batch().lines.iter().map(|(w, l)| (*w, l.list.len())).collect();
+        let sprites_before = self.batch().sprites.list.len();
+        let rects_before = self.batch().rects.list.len();
+        let texts_before = self.batch().texts.list.len();
+
+        self.push_transform(t);
+        for command in commands {
+            self.issue(command, key.tint, None, None);
+        }
+        self.pop_transform();
+
+        let batch = self.batch();
+        let lines = batch.lines
+            .iter()
+            .map(|(w, l)| {
+                let start = lines_before.get(w).cloned().unwrap_or(0);
+                (*w, since(&l.list, &l.order, start, base))
+            })
+            .filter(|&(_, ref l)| !l.is_empty())
+            .collect();
+        let sprites = since(&batch.sprites.list, &batch.sprites.order, sprites_before, base);
+        let rects = since(&batch.rects.list, &batch.rects.order, rects_before, base);
+        let texts = since(&batch.texts.list, &batch.texts.order, texts_before, base);
+
+        Generated {
+            key: key,
+            lines: lines,
+            sprites: sprites,
+            rects: rects,
+            texts: texts,
+            count: self.count - base,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use maths::vec2;
+    use AssetsMgrBuilder;
+    use StreamLineBackend;
+    use tests::TestBE;
+
+    #[test]
+    fn replay() {
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+
+        let mut grid = CommandList::new();
+        grid.line(vec2(0, 0), vec2(100, 0), 1, 0);
+        grid.rect(vec2(0, 0), vec2(10, 10), 1).with_color(1.0, 0.5, 0.0, 1.0);
+        grid.line(vec2(0, 0), vec2(0, 100), 2, 0).with_color(0.0, 0.0, 1.0, 1.0);
+        assert_eq!(grid.len(), 3);
+
+        let red = [1.0, 0.0, 0.0, 1.0];
+
+        let mut frame = |grid: &mut CommandList| {
+            let mut q = CmdQueue::new(be.surface(), &ass);
+            q.rect(vec2(0, 0), vec2(1, 1), 0);
+            q.replay(grid, Transform::translate(400.0, 0.0), red);
+            let lines = q.batch().lines[&1].list.clone();
+            let rects = q.batch().rects.list.clone();
+            let order = q.batch().rects.order.clone();
+            (lines, rects, order, q.count)
+        };
+
+        let (lines, rects, order, count) = frame(&mut grid);
+        assert_eq!(count, 4);
+        assert_eq!(order, vec![0, 2]);
+        let LineLayout(l) = lines[0];
+        assert_eq!(l[1], 0.0);
+        assert_eq!((l[5], l[6], l[7]), (1.0, 0.0, 0.0));
+        let RectLayout(r) = rects[1];
+        assert_eq!((r[7], r[8], r[9]), (1.0, 0.0, 0.0));
+
+        // next frame, copied from the list
+        assert!(grid.generated.is_some());
+        let again = frame(&mut grid);
+        assert!(again.0 == lines && again.1 == rects);
+        assert_eq!((again.2, again.3), (order, count));
+
+        // recording more drops what was generated
+        grid.sprite(vec2(0, 0), 0, 0);
+        assert!(grid.generated.is_none());
+    }
+
+    #[test]
+    fn tint() {
+        use BlendMode;
+        use find_folder::Search;
+        use std::path::Path;
+
+        let assets = Search::Parents(3).for_folder("assets").expect("some assets folder must exist somewhere");
+        let mut be = TestBE::default();
+        let (ass, sp, vector, bitmap) = {
+            let mut builder = AssetsMgrBuilder::new(&mut be);
+            let sp = builder.add_sprite(&assets.join(Path::new("rust-logo.png")));
+            let vector = builder.add_font(&assets.join(Path::new("OpenSans-Regular.ttf"))).expect("font");
+            let bitmap = builder.add_bitmap_font(&assets.join(Path::new("pixel-font.fnt"))).expect("font");
+            (builder.build().expect("assets"), sp, vector, bitmap)
+        };
+
+        let mut list = CommandList::new();
+        list.sprite(vec2(0, 0), 0, sp);
+        list.text(vec2(0, 0), 0, bitmap, "AV");
+        list.text(vec2(0, 0), 0, vector, "AV");
+
+        // every primitive is multiplied by the tint, sprites and glyphs too
+        let tint = [0.5, 1.0, 1.0, 0.5];
+        let mut q = CmdQueue::new(be.surface(), &ass);
+        q.replay(&mut list, Transform::identity(), tint);
+        let colors: Vec<_> = q.batch().sprites.iter().map(|&SpriteLayout(l)| [l[11], l[12], l[13], l[14]]).collect();
+        assert_eq!(colors, vec![tint; 3]);
+        assert_eq!(q.batch().texts[0].color, (0.5, 1.0, 1.0, 0.5));
+
+        // texts keep the blending they are issued with, the queue does not
+        let text = Command::Text {
+            pos: vec2(0, 0),
+            layer: 0,
+            font: vector,
+            text: "AV".to_string(),
+        };
+        q.issue(&text, tint, Some(BlendMode::Additive), None);
+        assert_eq!(q.batches.len(), 2);
+        assert_eq!(q.batches[1].state.blend, BlendMode::Additive);
+        assert_eq!(q.batches[1].texts.len(), 1);
+        assert_eq!(q.batch().state.blend, BlendMode::Alpha);
+    }
+}
@@ -0,0 +1,569 @@
+//! Frames recorded ahead of time, without a surface, and replayed into a queue
+//! when submitted. They can be sent between threads and merged.
+
+use CmdQueue;
+use StreamLineBackendSurface;
+use AssetsMgr;
+use SpriteId;
+use FontId;
+use Color;
+use Colorize;
+use Blending;
+use Shading;
+use Contour;
+use BlendMode;
+use RenderTarget;
+use MaterialId;
+use DrawOrder;
+use MaskStage;
+use command_list::Command;
+use maths::Vec2;
+use maths::Transform;
+use maths::Origin;
+use camera::Camera;
+use resolution::VirtualResolution;
+
+// the lines around a rect, tuned on their own like in the queue
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Border {
+    width: u32,
+    color: Color,
+    blend: Option<BlendMode>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Op {
+    Clear(Color),
+    Draw {
+        command: Command,
+        blend: Option<BlendMode>,
+        material: Option<MaterialId>,
+        border: Option<Border>,
+    },
+    Target {
+        pos: Vec2,
+        layer: i32,
+        target: RenderTarget,
+        blend: Option<BlendMode>,
+        material: Option<MaterialId>,
+    },
+    PushTransform(Transform),
+    PopTransform,
+    PushClip(Vec2, Vec2),
+    PopClip,
+    BeginMask,
+    EndMask,
+    PopMask,
+    Camera(Option<Camera>),
+    Origin(Origin),
+    Resolution(Option<VirtualResolution>),
+    DrawOrder(DrawOrder),
+    Uniform(MaterialId, String, f32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    ops: Vec<Op>,
+}
+
+/// why a part can not be merged, with its position in the list
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MergeError {
+    /// pops something it did not push, or leaves something pushed
+    Unbalanced(usize),
+    /// sets the camera, the origin, the virtual resolution, the draw order
+    /// or a uniform, which would leak into the next parts. set them on the
+    /// merged frame instead
+    State(usize),
+}
+
+/// tunes the last primitive recorded, like the tunes of the queue. after
+/// with_border, the tunes apply to the border
+pub struct FrameTune<'a> {
+    op: &'a mut Op,
+    border: bool,
+}
+
+impl<'a> FrameTune<'a> {
+    /// like Contour::with_border, for rects only
+    pub fn with_border(self, width: u32) -> FrameTune<'a> {
+        let mut tuned = false;
+        if let Op::Draw { command: Command::Rect { .. }, ref mut border, .. } = *self.op {
+            *border = Some(Border {
+                width: width,
+                color: [1.0, 1.0, 1.0, 1.0],
+                blend: None,
+            });
+            tuned = true;
+        }
+        FrameTune {
+            op: self.op,
+            border: tuned,
+        }
+    }
+}
+
+impl<'a> Colorize for FrameTune<'a> {
+    fn with_color(self, r: f32, g: f32, b: f32, a: f32) -> Self {
+        match *self.op {
+            Op::Draw { border: Some(ref mut border), .. } if self.border => border.color = [r, g, b, a],
+            Op::Draw { ref mut command, .. } => {
+                match *command {
+                    Command::Line { ref mut color, .. } |
+                    Command::Rect { ref mut color, .. } => *color = [r, g, b, a],
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        self
+    }
+}
+
+impl<'a> Blending for FrameTune<'a> {
+    fn with_blend(self, mode: BlendMode) -> Self {
+        match *self.op {
+            Op::Draw { border: Some(ref mut border), .. } if self.border => border.blend = Some(mode),
+            Op::Draw { ref mut blend, .. } |
+            Op::Target { ref mut blend, .. } => *blend = Some(mode),
+            _ => {}
+        }
+        self
+    }
+}
+
+impl<'a> Shading for FrameTune<'a> {
+    fn with_material(self, id: MaterialId) -> Self {
+        match *self.op {
+            // borders are lines, they have no material
+            _ if self.border => {}
+            Op::Draw { command: Command::Sprite { .. }, ref mut material, .. } |
+            Op::Draw { command: Command::Rect { .. }, ref mut material, .. } |
+            Op::Target { ref mut material, .. } => *material = Some(id),
+            _ => {}
+        }
+        self
+    }
+}
+
+impl Frame {
+    pub fn new() -> Frame {
+        Frame { ops: Vec::new() }
+    }
+
+    pub fn clear(&mut self, color: &Color) {
+        self.ops.push(Op::Clear(*color));
+    }
+
+    pub fn line(&mut self, src: Vec2, dst: Vec2, width: u32, layer: i32) -> FrameTune {
+        self.draw(Command::Line {
+            src: src,
+            dst: dst,
+            width: width,
+            layer: layer,
+            color: [1.0, 1.0, 1.0, 1.0],
+        })
+    }
+
+    pub fn sprite(&mut self, pos: Vec2, layer: i32, sprite: SpriteId) -> FrameTune {
+        self.draw(Command::Sprite {
+            pos: pos,
+            layer: layer,
+            sprite: sprite,
+        })
+    }
+
+    pub fn rect(&mut self, pos: Vec2, size: Vec2, layer: i32) -> FrameTune {
+        self.draw(Command::Rect {
+            pos: pos,
+            size: size,
+            layer: layer,
+            color: [0.0, 0.0, 0.0, 1.0],
+        })
+    }
+
+    pub fn text(&mut self, pos: Vec2, layer: i32, font: FontId, txt: &str) {
+        self.draw(Command::Text {
+            pos: pos,
+            layer: layer,
+            font: font,
+            text: txt.to_string(),
+        });
+    }
+
+    /// the content of a render target, as it is when the frame is drawn
+    pub fn target_sprite(&mut self, pos: Vec2, layer: i32, target: &RenderTarget) -> FrameTune {
+        self.ops.push(Op::Target {
+            pos: pos,
+            layer: layer,
+            target: *target,
+            blend: None,
+            material: None,
+        });
+        FrameTune {
+            op: self.ops.last_mut().unwrap(),
+            border: false,
+        }
+    }
+
+    pub fn push_transform(&mut self, t: Transform) {
+        self.ops.push(Op::PushTransform(t));
+    }
+
+    pub fn pop_transform(&mut self) {
+        self.ops.push(Op::PopTransform);
+    }
+
+    pub fn push_clip(&mut self, position: Vec2, dimensions: Vec2) {
+        self.ops.push(Op::PushClip(position, dimensions));
+    }
+
+    pub fn pop_clip(&mut self) {
+        self.ops.push(Op::PopClip);
+    }
+
+    pub fn begin_mask(&mut self) {
+        self.ops.push(Op::BeginMask);
+    }
+
+    pub fn end_mask(&mut self) {
+        self.ops.push(Op::EndMask);
+    }
+
+    pub fn pop_mask(&mut self) {
+        self.ops.push(Op::PopMask);
+    }
+
+    pub fn set_camera(&mut self, camera: Option<Camera>) {
+        self.ops.push(Op::Camera(camera));
+    }
+
+    pub fn set_origin(&mut self, origin: Origin) {
+        self.ops.push(Op::Origin(origin));
+    }
+
+    pub fn set_virtual_resolution(&mut self, resolution: Option<VirtualResolution>) {
+        self.ops.push(Op::Resolution(resolution));
+    }
+
+    pub fn set_draw_order(&mut self, order: DrawOrder) {
+        self.ops.push(Op::DrawOrder(order));
+    }
+
+    pub fn set_uniform(&mut self, material: MaterialId, name: &str, value: f32) {
+        self.ops.push(Op::Uniform(material, name.to_string(), value));
+    }
+
+    /// adds the calls of another frame after the ones of this one
+    pub fn append(&mut self, other: Frame) {
+        self.ops.extend(other.ops);
+    }
+
+    /// one frame out of parts recorded in parallel. the queue sorts the
+    /// primitives by layer, and those in the same layer by the position of
+    /// their part in the list, so the result does not depend on which
+    /// thread finished first. every part starts with the state the others
+    /// found: it pops all it pushes, and changes nothing else
+    pub fn merge<I: IntoIterator<Item = Frame>>(parts: I) -> Result<Frame, MergeError> {
+        let mut frame = Frame::new();
+        for (i, part) in parts.into_iter().enumerate() {
+            if part.changes_state() {
+                return Err(MergeError::State(i));
+            }
+            if !part.balanced() {
+                return Err(MergeError::Unbalanced(i));
+            }
+            frame.append(part);
+        }
+        Ok(frame)
+    }
+
+    // every pop after its push, and nothing left pushed. masks go through
+    // begin, end and pop, in that order
+    fn balanced(&self) -> bool {
+        let (mut transforms, mut clips) = (0i32, 0i32);
+        let mut masks = Vec::new();
+        for op in &self.ops {
+            match *op {
+                Op::PushTransform(_) => transforms += 1,
+                Op::PopTransform => transforms -= 1,
+                Op::PushClip(..) => clips += 1,
+                Op::PopClip => clips -= 1,
+                Op::BeginMask => masks.push(MaskStage::Write),
+                Op::EndMask => {
+                    match masks.last_mut() {
+                        Some(stage) if *stage == MaskStage::Write => *stage = MaskStage::Draw,
+                        _ => return false,
+                    }
+                }
+                Op::PopMask => {
+                    if masks.pop() != Some(MaskStage::Draw) {
+                        return false;
+                    }
+                }
+                _ => {}
+            }
+            if transforms < 0 || clips < 0 {
+                return false;
+            }
+        }
+        transforms == 0 && clips == 0 && masks.is_empty()
+    }
+
+    fn changes_state(&self) -> bool {
+        self.ops.iter().any(|op| match *op {
+            Op::Camera(_) | Op::Origin(_) | Op::Resolution(_) | Op::DrawOrder(_) | Op::Uniform(..) => true,
+            _ => false,
+        })
+    }
+
+    /// issues everything recorded into a queue, as if the calls were made to it
+    pub fn draw_into<S>(&self, q: &mut CmdQueue<S>)
+        where S: StreamLineBackendSurface
+    {
+        let white = [1.0, 1.0, 1.0, 1.0];
+        for op in &self.ops {
+            match *op {
+                Op::Clear(ref color) => q.clear(color),
+                Op::Draw { command: Command::Rect { pos, size, layer, color }, blend, material, border: Some(border) } => {
+                    let mut tune = q.rect(pos, size, layer).with_color(color[0], color[1], color[2], color[3]);
+                    if let Some(blend) = blend {
+                        tune = tune.with_blend(blend);
+                    }
+                    if let Some(material) = material {
+                        tune = tune.with_material(material);
+                    }
+                    let c = border.color;
+                    let lines = tune.with_border(border.width).with_color(c[0], c[1], c[2], c[3]);
+                    if let Some(blend) = border.blend {
+                        lines.with_blend(blend);
+                    }
+                }
+                Op::Draw { ref command, blend, material, .. } => q.issue(command, white, blend, material),
+                Op::Target { pos, layer, ref target, blend, material } => {
+                    let mut tune = q.target_sprite(pos, layer, target);
+                    if let Some(blend) = blend {
+                        tune = tune.with_blend(blend);
+                    }
+                    if let Some(material) = material {
+                        tune.with_material(material);
+                    }
+                }
+                Op::PushTransform(t) => q.push_transform(t),
+                Op::PopTransform => q.pop_transform(),
+                Op::PushClip(position, dimensions) => q.push_clip(position, dimensions),
+                Op::PopClip => q.pop_clip(),
+                Op::BeginMask => q.begin_mask(),
+                Op::EndMask => q.end_mask(),
+                Op::PopMask => q.pop_mask(),
+                Op::Camera(camera) => q.set_camera(camera),
+                Op::Origin(origin) => q.set_origin(origin),
+                Op::Resolution(resolution) => q.set_virtual_resolution(resolution),
+                Op::DrawOrder(order) => q.set_draw_order(order),
+                Op::Uniform(material, ref name, value) => q.set_uniform(material, name, value),
+            }
+        }
+    }
+
+    /// draws the frame into the surface, it can be submitted again to another one
+    pub fn submit<S>(&self, surface: S, assets: &AssetsMgr)
+        where S: StreamLineBackendSurface
+    {
+        let mut q = CmdQueue::new(surface, assets);
+        self.draw_into(&mut q);
+        q.done();
+    }
+
+    fn draw(&mut self, command: Command) -> FrameTune {
+        self.ops.push(Op::Draw {
+            command: command,
+            blend: None,
+            material: None,
+            border: None,
+        });
+        FrameTune {
+            op: self.ops.last_mut().unwrap(),
+            border: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use maths::vec2;
+    use AssetsMgrBuilder;
+    use StreamLineBackend;
+    use RectLayout;
+    use LineLayout;
+    use tests::TestBE;
+
+    use std::thread;
+
+    // what the queue would draw, batch by batch
+    fn summary<S: StreamLineBackendSurface>(q: &CmdQueue<S>) -> Vec<(BlendMode, Vec<RectLayout>, Vec<LineLayout>)> {
+        q.batches
+            .iter()
+            .map(|b| {
+                let lines = b.lines.get(&1).map_or(Vec::new(), |l| l.list.clone());
+                (b.state.blend, b.rects.list.clone(), lines)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn submit() {
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+
+        // recorded on another thread
+        let frame = thread::spawn(|| {
+                let mut f = Frame::new();
+                f.clear(&[0.0, 0.0, 0.0, 1.0]);
+                f.set_origin(Origin::TopLeft);
+                f.push_transform(Transform::translate(10.0, 10.0));
+                f.rect(vec2(0, 0), vec2(10, 10), 1).with_color(1.0, 0.0, 0.0, 1.0);
+                f.line(vec2(0, 0), vec2(10, 10), 1, 2).with_blend(BlendMode::Additive);
+                f.pop_transform();
+                f.push_clip(vec2(0, 0), vec2(100, 100));
+                f.rect(vec2(5, 5), vec2(10, 10), 0);
+                f.pop_clip();
+                f
+            })
+            .join()
+            .expect("recording does not fail");
+
+        let mut direct = CmdQueue::new(be.surface(), &ass);
+        direct.clear(&[0.0, 0.0, 0.0, 1.0]);
+        direct.set_origin(Origin::TopLeft);
+        direct.push_transform(Transform::translate(10.0, 10.0));
+        direct.rect(vec2(0, 0), vec2(10, 10), 1).with_color(1.0, 0.0, 0.0, 1.0);
+        direct.line(vec2(0, 0), vec2(10, 10), 1, 2).with_blend(BlendMode::Additive);
+        direct.pop_transform();
+        direct.push_clip(vec2(0, 0), vec2(100, 100));
+        direct.rect(vec2(5, 5), vec2(10, 10), 0);
+        direct.pop_clip();
+
+        let mut replayed = CmdQueue::new(be.surface(), &ass);
+        frame.draw_into(&mut replayed);
+        assert!(summary(&direct) == summary(&replayed));
+        assert_eq!(summary(&replayed).len(), 3);
+
+        // once per window
+        frame.submit(be.surface(), &ass);
+        frame.submit(be.surface(), &ass);
+    }
+
+    #[test]
+    fn merge() {
+        use std::sync::mpsc;
+
+        // one part per chunk of the world, they finish in any order
+        let (tx, rx) = mpsc::channel();
+        for chunk in 0..4 {
+            let tx = tx.clone();
+            thread::spawn(move || {
+                let mut f = Frame::new();
+                f.push_transform(Transform::translate(chunk as f32 * 100.0, 0.0));
+                f.rect(vec2(0, 0), vec2(10, 10), 3 - chunk);
+                f.pop_transform();
+                tx.send((chunk, f)).unwrap();
+            });
+        }
+        drop(tx);
+        let mut parts: Vec<(i32, Frame)> = rx.iter().collect();
+        parts.sort_by_key(|&(chunk, _)| chunk);
+
+        let merged = Frame::merge(parts.into_iter().map(|(_, f)| f)).expect("balanced parts");
+        let mut again = Frame::new();
+        for chunk in 0..4 {
+            again.push_transform(Transform::translate(chunk as f32 * 100.0, 0.0));
+            again.rect(vec2(0, 0), vec2(10, 10), 3 - chunk);
+            again.pop_transform();
+        }
+        assert_eq!(merged, again);
+
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+        let mut q = CmdQueue::new(be.surface(), &ass);
+        merged.draw_into(&mut q);
+        // the last chunk is the furthest
+        let rects = summary(&q)[0].1.clone();
+        let layers: Vec<f32> = rects.iter().map(|&RectLayout(r)| r[0]).collect();
+        assert_eq!(layers, vec![3.0, 2.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn targets_and_borders() {
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+        let target = be.add_target(200, 100).expect("target");
+
+        let mut f = Frame::new();
+        f.target_sprite(vec2(10, 10), 0, &target).with_blend(BlendMode::Premultiplied);
+        f.rect(vec2(0, 0), vec2(10, 10), 1)
+            .with_color(1.0, 0.0, 0.0, 1.0)
+            .with_border(1)
+            .with_color(0.0, 1.0, 0.0, 1.0);
+
+        let mut direct = CmdQueue::new(be.surface(), &ass);
+        direct.target_sprite(vec2(10, 10), 0, &target).with_blend(BlendMode::Premultiplied);
+        direct.rect(vec2(0, 0), vec2(10, 10), 1)
+            .with_color(1.0, 0.0, 0.0, 1.0)
+            .with_border(1)
+            .with_color(0.0, 1.0, 0.0, 1.0);
+
+        let mut replayed = CmdQueue::new(be.surface(), &ass);
+        f.draw_into(&mut replayed);
+        assert!(summary(&direct) == summary(&replayed));
+        let sprites = |q: &CmdQueue<_>| {
+            q.batches.iter().map(|b| (b.state, b.sprites.list.clone())).collect::<Vec<_>>()
+        };
+        assert!(sprites(&direct) == sprites(&replayed));
+
+        // the rect keeps its color, the border has its own
+        let (_, rects, lines) = summary(&replayed).remove(0);
+        assert_eq!(rects[0].0[7], 1.0);
+        assert_eq!(lines.len(), 4);
+        assert_eq!((lines[0].0[5], lines[0].0[6]), (0.0, 1.0));
+    }
+
+    #[test]
+    fn merge_rejected() {
+        let mut f = Frame::new();
+        f.push_clip(vec2(0, 0), vec2(10, 10));
+        assert_eq!(Frame::merge(vec![Frame::new(), f]), Err(MergeError::Unbalanced(1)));
+
+        // balanced in count, but popped before it is pushed
+        let mut f = Frame::new();
+        f.pop_transform();
+        f.push_transform(Transform::translate(1.0, 0.0));
+        assert_eq!(Frame::merge(vec![f]), Err(MergeError::Unbalanced(0)));
+
+        // masks are popped once drawn into, and ended only once
+        let mut f = Frame::new();
+        f.begin_mask();
+        f.pop_mask();
+        assert_eq!(Frame::merge(vec![f]), Err(MergeError::Unbalanced(0)));
+        let mut f = Frame::new();
+        f.begin_mask();
+        f.end_mask();
+        f.end_mask();
+        f.pop_mask();
+        assert_eq!(Frame::merge(vec![f]), Err(MergeError::Unbalanced(0)));
+        let mut f = Frame::new();
+        f.begin_mask();
+        f.end_mask();
+        f.pop_mask();
+        assert!(Frame::merge(vec![f]).is_ok());
+
+        // the camera would move the parts after it too
+        let mut f = Frame::new();
+        f.set_camera(None);
+        assert_eq!(Frame::merge(vec![f]), Err(MergeError::State(0)));
+        let mut f = Frame::new();
+        f.set_uniform(0, "amount", 0.5);
+        assert_eq!(Frame::merge(vec![f]), Err(MergeError::State(0)));
+    }
+}
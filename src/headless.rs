@@ -0,0 +1,575 @@
+//! A backend drawing on the cpu into images, following the rules of the glium
+//! backend, for tests and tools that run without a gpu.
+
+use StreamLineBackend;
+use StreamLineBackendSurface;
+use RenderTarget;
+use Material;
+use MaterialId;
+use VectorFont;
+use Color;
+use BlendMode;
+use Mask;
+use MaskStage;
+use layer_depth;
+use LineLayout;
+use SpriteLayout;
+use RectLayout;
+use TextLayout;
+use post;
+use post::PostEffect;
+use resolution::Viewport;
+use tools::RcRef;
+
+use image::Rgba;
+use image::RgbaImage;
+
+use std::io;
+use std::collections::BTreeMap as Map;
+
+/// size in pixels texts are rasterized at
+pub const FONT_SIZE: f32 = 24.0;
+
+pub struct HeadlessBackend {
+    dimensions: (u32, u32),
+    // textures and render targets share the ids, like in the glium backend
+    textures: RcRef<Map<u32, RgbaImage>>,
+    fonts: RcRef<Vec<VectorFont>>,
+    materials: u32,
+    effects: Vec<PostEffect>,
+    frame: RcRef<RgbaImage>,
+}
+
+impl HeadlessBackend {
+    /// a window of this size, black until the first frame is done
+    pub fn new(width: u32, height: u32) -> HeadlessBackend {
+        HeadlessBackend {
+            dimensions: (width, height),
+            textures: RcRef::new(Map::new()),
+            fonts: RcRef::new(Vec::new()),
+            materials: 0,
+            effects: Vec::new(),
+            frame: RcRef::new(RgbaImage::new(width, height)),
+        }
+    }
+
+    /// the last frame done on the window, post effects applied
+    pub fn frame(&self) -> RgbaImage {
+        self.frame.get().clone()
+    }
+
+    /// the content of a texture or a render target
+    pub fn texture(&self, id: u32) -> Option<RgbaImage> {
+        self.textures.get().get(&id).cloned()
+    }
+
+    fn new_surface(&self, target: Option<u32>, canvas: Canvas) -> HeadlessSurface {
+        HeadlessSurface {
+            canvas: canvas,
+            target: target,
+            textures: self.textures.clone(),
+            fonts: self.fonts.clone(),
+            effects: self.effects.clone(),
+            frame: self.frame.clone(),
+            viewport: None,
+            scissor: None,
+            mask: None,
+            blend: BlendMode::Alpha,
+        }
+    }
+}
+
+impl StreamLineBackend for HeadlessBackend {
+    type Surface = HeadlessSurface;
+
+    fn add_texture(&mut self, img: RgbaImage) -> u32 {
+        let id = self.textures.get().len() as u32;
+        self.textures.get_mut().insert(id, img);
+        id
+    }
+
+    fn add_font(&mut self, font: VectorFont) -> io::Result<u32> {
+        let id = self.fonts.get().len() as u32;
+        self.fonts.get_mut().push(font);
+        Ok(id)
+    }
+
+    fn add_material(&mut self, _material: &Material) -> io::Result<MaterialId> {
+        self.materials += 1;
+        Ok(self.materials - 1)
+    }
+
+    fn set_post_effects(&mut self, effects: &[PostEffect]) -> io::Result<()> {
+        self.effects = effects.to_vec();
+        Ok(())
+    }
+
+    fn surface(&mut self) -> HeadlessSurface {
+        let (w, h) = self.dimensions;
+        self.new_surface(None, Canvas::new(w, h))
+    }
+
+    fn add_target(&mut self, width: u32, height: u32) -> io::Result<RenderTarget> {
+        Ok(RenderTarget {
+            texture: self.add_texture(RgbaImage::new(width, height)),
+            width: width,
+            height: height,
+        })
+    }
+
+    fn target_surface(&mut self, target: &RenderTarget) -> HeadlessSurface {
+        // targets keep what was drawn before, until cleared
+        let canvas = Canvas::from_image(&self.textures.get()[&target.texture]);
+        self.new_surface(Some(target.texture), canvas)
+    }
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+// color, depth and stencil, rows from the bottom like the gpu sees them
+struct Canvas {
+    width: u32,
+    height: u32,
+    color: Vec<[f32; 4]>,
+    depth: Vec<f32>,
+    stencil: Vec<u32>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Canvas {
+        let n = (width * height) as usize;
+        Canvas {
+            width: width,
+            height: height,
+            color: vec![[0.0, 0.0, 0.0, 1.0]; n],
+            depth: vec![1.0; n],
+            stencil: vec![0; n],
+        }
+    }
+
+    fn from_image(img: &RgbaImage) -> Canvas {
+        let (width, height) = img.dimensions();
+        let mut canvas = Canvas::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let p = img.get_pixel(x, height - 1 - y).data;
+                canvas.color[(y * width + x) as usize] = [p[0] as f32 / 255.0,
+                                                          p[1] as f32 / 255.0,
+                                                          p[2] as f32 / 255.0,
+                                                          p[3] as f32 / 255.0];
+            }
+        }
+        canvas
+    }
+
+    fn to_image(&self) -> RgbaImage {
+        let mut img = RgbaImage::new(self.width, self.height);
+        let c = |v: f32| (v.max(0.0).min(1.0) * 255.0).round() as u8;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let p = self.color[(y * self.width + x) as usize];
+                img.put_pixel(x, self.height - 1 - y, Rgba([c(p[0]), c(p[1]), c(p[2]), c(p[3])]));
+            }
+        }
+        img
+    }
+}
+
+fn blend(mode: BlendMode, src: [f32; 4], dst: [f32; 4]) -> [f32; 4] {
+    let a = src[3];
+    let mut out = [0.0; 4];
+    for k in 0..4 {
+        let (s, d) = (src[k], dst[k]);
+        // multiply and screen work on the colors multiplied by their alpha
+        let p = if k < 3 { s * a } else { a };
+        out[k] = match mode {
+            BlendMode::Alpha => s * a + d * (1.0 - a),
+            BlendMode::Additive => s * a + d,
+            BlendMode::Multiply => p * d + d * (1.0 - a),
+            BlendMode::Screen => p + d * (1.0 - p),
+            BlendMode::Premultiplied => s + d * (1.0 - a),
+        };
+    }
+    out
+}
+
+fn sample(img: &RgbaImage, u: f32, v: f32) -> [f32; 4] {
+    // textures are uploaded upside down, v goes up from the last row
+    let (w, h) = img.dimensions();
+    let x = ((u * w as f32) as i32).max(0).min(w as i32 - 1) as u32;
+    let y = (((1.0 - v) * h as f32) as i32).max(0).min(h as i32 - 1) as u32;
+    let p = img.get_pixel(x, y).data;
+    [p[0] as f32 / 255.0, p[1] as f32 / 255.0, p[2] as f32 / 255.0, p[3] as f32 / 255.0]
+}
+
+pub struct HeadlessSurface {
+    canvas: Canvas,
+    // None is the window
+    target: Option<u32>,
+    textures: RcRef<Map<u32, RgbaImage>>,
+    fonts: RcRef<Vec<VectorFont>>,
+    effects: Vec<PostEffect>,
+    frame: RcRef<RgbaImage>,
+    viewport: Option<Viewport>,
+    scissor: Option<Viewport>,
+    mask: Option<Mask>,
+    blend: BlendMode,
+}
+
+impl HeadlessSurface {
+    fn area(&self) -> Viewport {
+        self.viewport.unwrap_or(Viewport {
+            left: 0,
+            bottom: 0,
+            width: self.canvas.width,
+            height: self.canvas.height,
+        })
+    }
+
+    // from normalized coordinates to pixels
+    fn to_pixels(&self, x: f32, y: f32) -> (f32, f32) {
+        let vp = self.area();
+        (vp.left as f32 + (x + 1.0) / 2.0 * vp.width as f32,
+         vp.bottom as f32 + (y + 1.0) / 2.0 * vp.height as f32)
+    }
+
+    // pixels that can be drawn, inside of the viewport and the scissor
+    fn bounds(&self, min: (f32, f32), max: (f32, f32)) -> (u32, u32, u32, u32) {
+        let mut vp = self.area();
+        if let Some(scissor) = self.scissor {
+            vp = vp.intersect(&scissor);
+        }
+        let right = (vp.left + vp.width).min(self.canvas.width) as f32;
+        let top = (vp.bottom + vp.height).min(self.canvas.height) as f32;
+        let x0 = min.0.floor().max(vp.left as f32).min(right);
+        let y0 = min.1.floor().max(vp.bottom as f32).min(top);
+        let x1 = max.0.ceil().max(x0).min(right);
+        let y1 = max.1.ceil().max(y0).min(top);
+        (x0 as u32, y0 as u32, x1 as u32, y1 as u32)
+    }
+
+    // one pixel of a primitive, through stencil, depth and blending
+    fn fragment(&mut self, x: u32, y: u32, layer: f32, color: [f32; 4]) {
+        let i = (y * self.canvas.width + x) as usize;
+        let depth = layer_depth(layer);
+
+        let stencil = self.canvas.stencil[i];
+        let hidden = match self.mask {
+            None => false,
+            Some(mask) => {
+                match mask.stage {
+                    MaskStage::Write => {
+                        if stencil == mask.depth - 1 {
+                            self.canvas.stencil[i] += 1;
+                        }
+                        true
+                    }
+                    MaskStage::Draw => {
+                        if stencil != mask.depth {
+                            return;
+                        }
+                        false
+                    }
+                    MaskStage::Erase => {
+                        if stencil == mask.depth {
+                            self.canvas.stencil[i] -= 1;
+                        }
+                        true
+                    }
+                }
+            }
+        };
+        // the shapes of a mask are not visible, and do not hide anything
+        if hidden || depth > self.canvas.depth[i] {
+            return;
+        }
+
+        self.canvas.depth[i] = depth;
+        self.canvas.color[i] = blend(self.blend, color, self.canvas.color[i]);
+    }
+
+    // fills the parallelogram p + s * u + t * v, shader gives the color at s, t
+    fn quad<F>(&mut self, layer: f32, p: (f32, f32), u: (f32, f32), v: (f32, f32), shader: F)
+        where F: Fn(f32, f32) -> [f32; 4]
+    {
+        let o = self.to_pixels(p.0, p.1);
+        let a = self.to_pixels(p.0 + u.0, p.1 + u.1);
+        let b = self.to_pixels(p.0 + v.0, p.1 + v.1);
+        let (u, v) = ((a.0 - o.0, a.1 - o.1), (b.0 - o.0, b.1 - o.1));
+        let det = u.0 * v.1 - u.1 * v.0;
+        if det == 0.0 {
+            return;
+        }
+
+        let xs = [o.0, a.0, b.0, a.0 + v.0];
+        let ys = [o.1, a.1, b.1, a.1 + v.1];
+        let min = (xs.iter().cloned().fold(1.0 / 0.0, f32::min), ys.iter().cloned().fold(1.0 / 0.0, f32::min));
+        let max = (xs.iter().cloned().fold(-1.0 / 0.0, f32::max), ys.iter().cloned().fold(-1.0 / 0.0, f32::max));
+        let (x0, y0, x1, y1) = self.bounds(min, max);
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                // pixel centers, in the coordinates of the quad
+                let (dx, dy) = (x as f32 + 0.5 - o.0, y as f32 + 0.5 - o.1);
+                let s = (dx * v.1 - dy * v.0) / det;
+                let t = (u.0 * dy - u.1 * dx) / det;
+                if s >= 0.0 && s < 1.0 && t >= 0.0 && t < 1.0 {
+                    let color = shader(s, t);
+                    self.fragment(x, y, layer, color);
+                }
+            }
+        }
+    }
+
+    fn line(&mut self, l: &[f32; 9], width: u32) {
+        let a = self.to_pixels(l[1], l[2]);
+        let b = self.to_pixels(l[3], l[4]);
+        let half = (width.max(1) as f32) / 2.0;
+        let min = (a.0.min(b.0) - half, a.1.min(b.1) - half);
+        let max = (a.0.max(b.0) + half, a.1.max(b.1) + half);
+        let (x0, y0, x1, y1) = self.bounds(min, max);
+
+        let d = (b.0 - a.0, b.1 - a.1);
+        let len2 = d.0 * d.0 + d.1 * d.1;
+        for y in y0..y1 {
+            for x in x0..x1 {
+                // distance from the pixel center to the segment
+                let p = (x as f32 + 0.5 - a.0, y as f32 + 0.5 - a.1);
+                let t = if len2 == 0.0 { 0.0 } else { ((p.0 * d.0 + p.1 * d.1) / len2).max(0.0).min(1.0) };
+                let (ex, ey) = (p.0 - d.0 * t, p.1 - d.1 * t);
+                if (ex * ex + ey * ey).sqrt() <= half {
+                    self.fragment(x, y, l[0], [l[5], l[6], l[7], l[8]]);
+                }
+            }
+        }
+    }
+
+    fn text(&mut self, text: &TextLayout, font: &VectorFont) {
+        let (mut cursor, base) = self.to_pixels(text.pos.0, text.pos.1);
+        let color = text.color;
+        let mut prev = None;
+        for c in text.text.chars() {
+            if let Some(prev) = prev {
+                cursor += font.kerning(FONT_SIZE, prev, c);
+            }
+            prev = Some(c);
+            let glyph = match font.rasterize(c, FONT_SIZE) {
+                Some(glyph) => glyph,
+                None => continue,
+            };
+
+            // rows of the bitmap go down from the top of the glyph
+            let left = cursor.round() as i32 + glyph.left;
+            let top = base.round() as i32 + glyph.top;
+            for row in 0..glyph.height {
+                for col in 0..glyph.width {
+                    let coverage = glyph.coverage[(row * glyph.width + col) as usize] as f32 / 255.0;
+                    let alpha = color.3 * coverage;
+                    let (x, y) = (left + col as i32, top - 1 - row as i32);
+                    if alpha <= 0.01 || x < 0 || y < 0 {
+                        continue;
+                    }
+                    let (x0, y0, x1, y1) = self.bounds((x as f32, y as f32), (x as f32 + 1.0, y as f32 + 1.0));
+                    if x0 < x1 && y0 < y1 {
+                        self.fragment(x0, y0, text.layer, [color.0, color.1, color.2, alpha]);
+                    }
+                }
+            }
+            cursor += glyph.advance;
+        }
+    }
+}
+
+impl StreamLineBackendSurface for HeadlessSurface {
+    fn dimensions(&self) -> (f32, f32) {
+        (self.canvas.width as f32, self.canvas.height as f32)
+    }
+
+    fn set_viewport(&mut self, viewport: Option<Viewport>) {
+        self.viewport = viewport;
+    }
+
+    fn set_scissor(&mut self, scissor: Option<Viewport>) {
+        self.scissor = scissor;
+    }
+
+    fn set_mask(&mut self, mask: Option<Mask>) {
+        self.mask = mask;
+    }
+
+    fn set_blend(&mut self, mode: BlendMode) {
+        self.blend = mode;
+    }
+
+    fn set_material(&mut self, _material: Option<MaterialId>) {}
+
+    fn set_uniform(&mut self, _material: MaterialId, _name: &str, _value: f32) {}
+
+    fn clear(&mut self, color: &Color) {
+        let vp = self.area();
+        for y in vp.bottom..(vp.bottom + vp.height).min(self.canvas.height) {
+            for x in vp.left..(vp.left + vp.width).min(self.canvas.width) {
+                let i = (y * self.canvas.width + x) as usize;
+                self.canvas.color[i] = *color;
+                self.canvas.depth[i] = 1.0;
+                self.canvas.stencil[i] = 0;
+            }
+        }
+    }
+
+    fn draw_sprites(&mut self, sprites: &[SpriteLayout], tex: u32) {
+        let textures = self.textures.clone();
+        let textures = textures.get();
+        let img = textures.get(&tex);
+        for &SpriteLayout(l) in sprites {
+            // tex coords from the bottom left, the top is at y
+            let color = [l[11], l[12], l[13], l[14]];
+            self.quad(l[0], (l[1], l[2]), (l[3], l[4]), (l[5], l[6]), |s, t| {
+                let texel = match img {
+                    Some(img) => sample(img, l[7] + s * l[9], l[8] - l[10] + t * l[10]),
+                    None => [1.0, 1.0, 1.0, 1.0],
+                };
+                [texel[0] * color[0], texel[1] * color[1], texel[2] * color[2], texel[3] * color[3]]
+            });
+        }
+    }
+
+    fn draw_lines(&mut self, lines: &[LineLayout], width: u32) {
+        for &LineLayout(ref l) in lines {
+            self.line(l, width);
+        }
+    }
+
+    fn draw_rects(&mut self, rects: &[RectLayout]) {
+        for &RectLayout(l) in rects {
+            let color = [l[7], l[8], l[9], l[10]];
+            self.quad(l[0], (l[1], l[2]), (l[3], l[4]), (l[5], l[6]), |_, _| color);
+        }
+    }
+
+    fn draw_texts(&mut self, texts: &[TextLayout]) {
+        let fonts = self.fonts.clone();
+        let fonts = fonts.get();
+        for text in texts {
+            if let Some(font) = fonts.get(text.font as usize) {
+                self.text(text, font);
+            }
+        }
+    }
+
+    fn done(self) {
+        let img = self.canvas.to_image();
+        match self.target {
+            Some(id) => {
+                let mut textures = self.textures.clone();
+                textures.get_mut().insert(id, img);
+            }
+            None => {
+                let mut frame = self.frame.clone();
+                *frame.get_mut() = if self.effects.is_empty() {
+                    img
+                } else {
+                    post::apply(&self.effects, &img)
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use CmdQueue;
+    use AssetsMgrBuilder;
+    use Colorize;
+    use Blending;
+    use maths::vec2;
+
+    fn pixel(img: &RgbaImage, x: u32, y: u32) -> [u8; 4] {
+        // from the bottom, like the queue
+        img.get_pixel(x, img.height() - 1 - y).data
+    }
+
+    #[test]
+    fn layers_and_blending() {
+        let mut be = HeadlessBackend::new(100, 100);
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+        {
+            let mut q = CmdQueue::new(be.surface(), &ass);
+            q.clear(&[0.0, 0.0, 1.0, 1.0]);
+            // the red one is on top, even if issued first
+            q.rect(vec2(10, 10), vec2(30, 30), 2).with_color(1.0, 0.0, 0.0, 1.0);
+            q.rect(vec2(20, 20), vec2(30, 30), 1).with_color(0.0, 1.0, 0.0, 1.0);
+            q.rect(vec2(60, 60), vec2(10, 10), 3)
+                .with_color(0.0, 1.0, 0.0, 1.0)
+                .with_blend(BlendMode::Additive);
+            q.line(vec2(0, 90), vec2(100, 90), 1, 0).with_color(1.0, 1.0, 1.0, 1.0);
+            q.done();
+        }
+        let img = be.frame();
+        assert_eq!(pixel(&img, 25, 25), [255, 0, 0, 255]);
+        assert_eq!(pixel(&img, 45, 45), [0, 255, 0, 255]);
+        assert_eq!(pixel(&img, 65, 65), [0, 255, 255, 255]);
+        assert_eq!(pixel(&img, 50, 90), [255, 255, 255, 255]);
+        assert_eq!(pixel(&img, 5, 5), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn translucent_multiply() {
+        let mut be = HeadlessBackend::new(100, 100);
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+        {
+            let mut q = CmdQueue::new(be.surface(), &ass);
+            q.clear(&[0.0, 0.6, 1.0, 1.0]);
+            q.rect(vec2(0, 0), vec2(50, 50), 1)
+                .with_color(0.0, 0.0, 0.0, 0.5)
+                .with_blend(BlendMode::Multiply);
+            q.rect(vec2(50, 0), vec2(50, 50), 1)
+                .with_color(0.0, 0.0, 0.0, 0.0)
+                .with_blend(BlendMode::Multiply);
+            q.rect(vec2(0, 50), vec2(50, 50), 1)
+                .with_color(1.0, 1.0, 1.0, 0.5)
+                .with_blend(BlendMode::Screen);
+            q.done();
+        }
+        let img = be.frame();
+        // half way to black, transparent leaves it as it was
+        assert_eq!(pixel(&img, 25, 25), [0, 77, 128, 255]);
+        assert_eq!(pixel(&img, 75, 25), [0, 153, 255, 255]);
+        assert_eq!(pixel(&img, 25, 75), [128, 204, 255, 255]);
+    }
+
+    #[test]
+    fn clips_masks_and_targets() {
+        let mut be = HeadlessBackend::new(100, 100);
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+        let target = be.add_target(10, 10).expect("target");
+        {
+            let mut t = CmdQueue::new(be.target_surface(&target), &ass);
+            t.clear(&[1.0, 1.0, 0.0, 1.0]);
+            t.done();
+        }
+        {
+            let mut q = CmdQueue::new(be.surface(), &ass);
+            q.clear(&[0.0, 0.0, 0.0, 1.0]);
+            q.push_clip(vec2(0, 0), vec2(50, 100));
+            q.rect(vec2(0, 0), vec2(100, 10), 0).with_color(1.0, 1.0, 1.0, 1.0);
+            q.pop_clip();
+            q.begin_mask();
+            q.rect(vec2(0, 50), vec2(10, 10), 0);
+            q.end_mask();
+            q.rect(vec2(0, 50), vec2(100, 10), 0).with_color(1.0, 0.0, 0.0, 1.0);
+            q.pop_mask();
+            q.target_sprite(vec2(80, 80), 0, &target);
+            q.done();
+        }
+        let img = be.frame();
+        assert_eq!(pixel(&img, 40, 5), [255, 255, 255, 255]);
+        assert_eq!(pixel(&img, 60, 5), [0, 0, 0, 255]);
+        assert_eq!(pixel(&img, 5, 55), [255, 0, 0, 255]);
+        assert_eq!(pixel(&img, 50, 55), [0, 0, 0, 255]);
+        assert_eq!(pixel(&img, 85, 85), [255, 255, 0, 255]);
+    }
+}
@@ -8,39 +8,88 @@ extern crate image;
 extern crate find_folder;
 extern crate rect_packer;
 extern crate time;
+extern crate rusttype;
 
 #[cfg(feature="profile")]
 extern crate flame;
 
 mod assets;
+mod bmfont;
+mod vector_font;
 pub mod tools;
 pub mod maths;
+pub mod camera;
+pub mod resolution;
+pub mod post;
+pub mod scene;
+pub mod command_list;
+pub mod frame;
+pub mod capture;
+pub mod headless;
+mod material;
 
 use image::RgbaImage;
 
 use std::vec::Vec;
 use std::collections::BTreeMap as Map;
 use std::ops::Range;
+use std::ops::{Deref, DerefMut};
+use std::marker::PhantomData;
 
-use tools::RcRef;
 use maths::Vec2;
+use maths::Transform;
+use maths::Origin;
+use camera::Camera;
+use resolution::Viewport;
+use resolution::VirtualResolution;
+use command_list::Command;
 
 pub type AssetsMgrBuilder<'a, BE> = assets::AssetsMgrBuilder<'a, BE>;
 pub type AssetsMgr = assets::AssetsMgr;
+pub type Manifest = assets::Manifest;
+pub type BitmapFont = bmfont::BitmapFont;
+pub type VectorFont = vector_font::VectorFont;
+pub type GlyphBitmap = vector_font::GlyphBitmap;
+pub type Material = material::Material;
 
 pub type SpriteId = usize;
 pub type FontId = usize;
+pub type MaterialId = u32;
 pub type Color = [f32; 4];
 
+/// layers from -LAYERS / 2 to LAYERS / 2 - 1 get a depth of their own, the
+/// others share the depth of the nearest end, and the queue warns about them
+pub const LAYERS: u32 = 4096;
+
+/// whether a layer gets a depth of its own
+pub fn layer_in_range(layer: i32) -> bool {
+    let half = (LAYERS / 2) as i32;
+    layer >= -half && layer < half
+}
+
+/// the depth a layer is drawn at, in (0, 1) and lower the higher the layer.
+/// it does not depend on what else is in the frame, so it is the same every frame
+pub fn layer_depth(layer: f32) -> f32 {
+    let half = (LAYERS / 2) as f32;
+    let slot = layer.max(-half).min(half - 1.0) + half + 1.0;
+    1.0 - slot / (LAYERS + 1) as f32
+}
+
+/// the layer of every layout is the one it was issued with, higher is nearer.
+/// backends draw it at layer_depth(layer)
+///
 /// sprite data layout:  offsets and sizes come from the texture atlas
-// { layer f32, pos(f32,f32), trg_size(f32, f32), sprite_offset(f32,f32), sprite_size(f32, f32) }
+/// the quad spans from pos along two edges, so it can be rotated or skewed.
+/// the texture is multiplied by the color, white leaves it as it is
+// { layer f32, pos(f32,f32), edge_u(f32, f32), edge_v(f32, f32), sprite_offset(f32,f32), sprite_size(f32, f32), color(f32,f32,f32,f32) }
 #[derive(PartialEq, Copy, Clone)]
-pub struct SpriteLayout(pub [f32; 9]);
+pub struct SpriteLayout(pub [f32; 15]);
 
-/// sprite data layout:  offsets and sizes come from the texture atlas
-// { layer f32, pos(f32,f32), size(f32, f32), color(f32,f32,f32, f32) }
+/// rectangle data layout:
+/// the quad spans from pos along two edges, so it can be rotated or skewed
+// { layer f32, pos(f32,f32), edge_u(f32, f32), edge_v(f32, f32), color(f32,f32,f32, f32) }
 #[derive(PartialEq, Copy, Clone)]
-pub struct RectLayout(pub [f32; 9]);
+pub struct RectLayout(pub [f32; 11]);
 
 /// line data layout:
 // { layer f32, src(f32, f32), trg(f32, f32), color(f32,f32,f32,f32) }
@@ -60,15 +109,16 @@ pub struct TextLayout {
 
 /// this struct provides the means to "tune" the primitives after being issued
 /// this struct is not meant to be directly used but instead implements the
-/// traits to colorize, add border, etc... when it proceeds
-pub struct LayoutTune<T> {
+/// traits to colorize, add border, etc... when it proceeds.
+/// it is just where the primitives are in the batches of the queue, which
+/// stays borrowed until the tune is dropped
+pub struct LayoutTune<'q, T> {
+    batches: &'q mut Batches,
+    batch: usize,
+    // only used by lines
+    width: u32,
     last: Range<usize>,
-    lastqueue: RcRef<Vec<T>>,
-
-    dimensions: (f32, f32),
-    lines: RcRef<Map<u32, RcRef<Vec<LineLayout>>>>,
-    _sprites: RcRef<Vec<SpriteLayout>>,
-    _rects: RcRef<Vec<RectLayout>>,
+    kind: PhantomData<T>,
 }
 
 /// this trait lets us color primitives
@@ -76,12 +126,10 @@ pub trait Colorize {
     fn with_color(self, r: f32, g: f32, b: f32, a: f32) -> Self;
 }
 
-impl Colorize for LayoutTune<LineLayout> {
+impl<'q> Colorize for LayoutTune<'q, LineLayout> {
     fn with_color(mut self, r: f32, g: f32, b: f32, a: f32) -> Self {
-        for i in self.last.clone() {
-            let queue = &mut self.lastqueue.get_mut();
-            let &mut LineLayout(ref mut elem) = queue.get_mut(i).unwrap();
-
+        let last = self.last.clone();
+        for &mut LineLayout(ref mut elem) in &mut self.list()[last] {
             elem[5] = r;
             elem[6] = g;
             elem[7] = b;
@@ -91,74 +139,193 @@ impl Colorize for LayoutTune<LineLayout> {
     }
 }
 
-impl Colorize for LayoutTune<RectLayout> {
+impl<'q> Colorize for LayoutTune<'q, SpriteLayout> {
     fn with_color(mut self, r: f32, g: f32, b: f32, a: f32) -> Self {
-        for i in self.last.clone() {
-            let queue = &mut self.lastqueue.get_mut();
-            let &mut RectLayout(ref mut elem) = queue.get_mut(i).unwrap();
+        let last = self.last.clone();
+        for &mut SpriteLayout(ref mut elem) in &mut self.list()[last] {
+            elem[11] = r;
+            elem[12] = g;
+            elem[13] = b;
+            elem[14] = a;
+        }
+        self
+    }
+}
 
-            elem[5] = r;
-            elem[6] = g;
-            elem[7] = b;
-            elem[8] = a;
+impl<'q> Colorize for LayoutTune<'q, RectLayout> {
+    fn with_color(mut self, r: f32, g: f32, b: f32, a: f32) -> Self {
+        let last = self.last.clone();
+        for &mut RectLayout(ref mut elem) in &mut self.list()[last] {
+            elem[7] = r;
+            elem[8] = g;
+            elem[9] = b;
+            elem[10] = a;
         }
         self
     }
 }
 
-/// trait to add a countour arround primitives
-pub trait Contour {
-    fn with_border(self, width: u32) -> LayoutTune<LineLayout>;
+/// trait to change how primitives are blended, they will be drawn in
+/// another batch than the primitives issued with the default blending
+pub trait Blending {
+    fn with_blend(self, mode: BlendMode) -> Self;
 }
 
-impl Contour for LayoutTune<RectLayout> {
-    fn with_border(mut self, width: u32) -> LayoutTune<LineLayout> {
+// the list of a batch holding the layouts of a kind
+trait Stored: Sized {
+    fn layouts(batch: &mut Batch, width: u32) -> &mut Layouts<Self>;
+}
 
-        let n = self.last.start;
-        assert_eq!(self.last.end, n + 1);
+impl Stored for LineLayout {
+    fn layouts(batch: &mut Batch, width: u32) -> &mut Layouts<LineLayout> {
+        batch.lines.entry(width).or_insert_with(Layouts::new)
+    }
+}
 
-        // read data from rectangle
-        let RectLayout(elem) = self.lastqueue.get_mut()[n];
-        let layer = elem[0] + 1.0;
-        let x = elem[1];
-        let y = elem[2];
-        let w = elem[3];
-        let h = elem[4];
+impl Stored for SpriteLayout {
+    fn layouts(batch: &mut Batch, _: u32) -> &mut Layouts<SpriteLayout> {
+        &mut batch.sprites
+    }
+}
+
+impl Stored for RectLayout {
+    fn layouts(batch: &mut Batch, _: u32) -> &mut Layouts<RectLayout> {
+        &mut batch.rects
+    }
+}
 
-        // add width if does not exist
-        if self.lines.get().get(&width).is_none() {
-            self.lines.get_mut().insert(width, RcRef::new(Vec::new()));
+impl<'q, T> LayoutTune<'q, T> {
+    fn new(batches: &'q mut Batches, batch: usize, width: u32, last: Range<usize>) -> LayoutTune<'q, T> {
+        LayoutTune {
+            batches: batches,
+            batch: batch,
+            width: width,
+            last: last,
+            kind: PhantomData,
         }
+    }
+
+    fn list(&mut self) -> &mut Layouts<T>
+        where T: Stored
+    {
+        T::layouts(&mut self.batches[self.batch], self.width)
+    }
+
+    fn state(&self) -> DrawState {
+        self.batches[self.batch].state
+    }
+
+    // moves the tuned primitives to the batch of another state
+    fn move_to(mut self, state: DrawState) -> LayoutTune<'q, T>
+        where T: Stored
+    {
+        let last = self.last.clone();
+        let moved = self.list().drain(last);
+
+        let batch = self.batches.index(state);
+        let start = {
+            let target = T::layouts(&mut self.batches[batch], self.width);
+            let start = target.len();
+            for (layout, seq) in moved {
+                target.push(layout, seq);
+            }
+            start
+        };
+        let end = start + self.last.len();
+        LayoutTune::new(self.batches, batch, self.width, start..end)
+    }
+}
+
+impl<'q> Blending for LayoutTune<'q, LineLayout> {
+    fn with_blend(self, mode: BlendMode) -> Self {
+        let state = DrawState { blend: mode, ..self.state() };
+        self.move_to(state)
+    }
+}
+
+impl<'q> Blending for LayoutTune<'q, SpriteLayout> {
+    fn with_blend(self, mode: BlendMode) -> Self {
+        let state = DrawState { blend: mode, ..self.state() };
+        self.move_to(state)
+    }
+}
+
+impl<'q> Blending for LayoutTune<'q, RectLayout> {
+    fn with_blend(self, mode: BlendMode) -> Self {
+        let state = DrawState { blend: mode, ..self.state() };
+        self.move_to(state)
+    }
+}
+
+/// trait to draw primitives with a material registered in the assets,
+/// primitives sharing a material are drawn in the same batch
+pub trait Shading {
+    fn with_material(self, material: MaterialId) -> Self;
+}
+
+impl<'q> Shading for LayoutTune<'q, SpriteLayout> {
+    fn with_material(self, material: MaterialId) -> Self {
+        let state = DrawState {
+            material: Some(material),
+            uniforms: self.batches.uniforms_of(material),
+            ..self.state()
+        };
+        self.move_to(state)
+    }
+}
+
+impl<'q> Shading for LayoutTune<'q, RectLayout> {
+    fn with_material(self, material: MaterialId) -> Self {
+        let state = DrawState {
+            material: Some(material),
+            uniforms: self.batches.uniforms_of(material),
+            ..self.state()
+        };
+        self.move_to(state)
+    }
+}
+
+/// trait to add a countour arround primitives
+pub trait Contour<'q> {
+    fn with_border(self, width: u32) -> LayoutTune<'q, LineLayout>;
+}
+
+impl<'q> Contour<'q> for LayoutTune<'q, RectLayout> {
+    fn with_border(mut self, width: u32) -> LayoutTune<'q, LineLayout> {
 
-        // do the containers dance
-        let lines_map_next = self.lines.clone();
-        let mut lines_map_rc = self.lines.get_mut();
-        let lines_list_rc = lines_map_rc.get_mut(&width).unwrap();
+        let width = self.batches.line_width(width);
+        let n = self.last.start;
+        assert_eq!(self.last.end, n + 1);
 
-        let i = lines_list_rc.get().len();
-        let lines_list_next = lines_list_rc.clone();
-        let mut list = lines_list_rc.get_mut();
+        // read data from rectangle, the border is issued with it
+        let (RectLayout(elem), seq) = {
+            let rects = self.list();
+            (rects[n], rects.order[n])
+        };
+        let layer = elem[0] + 1.0;
+        let (x, y) = (elem[1], elem[2]);
+        let (ux, uy) = (elem[3], elem[4]);
+        let (vx, vy) = (elem[5], elem[6]);
 
         // when using width greater than one, we need to add an extra lenght so we get sharp
         // corners
         // let hoff = (width as f32 / 2.0 + 1.0) / self.dimensions.0;
         // let voff = (width as f32 / 2.0 + 1.0) / self.dimensions.1;
 
-        // insert new elements
-        list.push(LineLayout([layer, x, y, x, y + w, 1.0, 1.0, 1.0, 1.0]));
-        list.push(LineLayout([layer, x + h, y, x + h, y + w, 1.0, 1.0, 1.0, 1.0]));
-        list.push(LineLayout([layer, x, y + w, x + h, y + w, 1.0, 1.0, 1.0, 1.0]));
-        list.push(LineLayout([layer, x, y, x + h, y, 1.0, 1.0, 1.0, 1.0]));
-
-        LayoutTune {
-            last: i..i + 4,
-            lastqueue: lines_list_next,
+        // insert new elements, one per edge, in the same batch
+        let i = {
+            let list = LineLayout::layouts(&mut self.batches[self.batch], width);
+            let i = list.len();
+            let corners = [(x, y), (x + ux, y + uy), (x + ux + vx, y + uy + vy), (x + vx, y + vy)];
+            for k in 0..4 {
+                let (x1, y1) = corners[k];
+                let (x2, y2) = corners[(k + 1) % 4];
+                list.push(LineLayout([layer, x1, y1, x2, y2, 1.0, 1.0, 1.0, 1.0]), seq);
+            }
+            i
+        };
 
-            dimensions: self.dimensions,
-            lines: lines_map_next,
-            _sprites: self._sprites,
-            _rects: self._rects,
-        }
+        LayoutTune::new(self.batches, self.batch, width, i..i + 4)
     }
 }
 
@@ -166,13 +333,44 @@ impl Contour for LayoutTune<RectLayout> {
 pub trait StreamLineBackend {
     type Surface;
     fn add_texture(&mut self, img: RgbaImage) -> u32;
-    fn add_font<FIO: std::io::Read>(&mut self, font: FIO) -> u32;
-    fn surface(&mut self, layers: u32) -> Self::Surface;
+    fn add_font(&mut self, font: VectorFont) -> std::io::Result<u32>;
+    /// compiles the material, errors tell what is wrong with the fragment
+    fn add_material(&mut self, material: &Material) -> std::io::Result<MaterialId>;
+    /// full screen passes run on every frame drawn to the window, in order.
+    /// fails when the backend can not run them
+    fn set_post_effects(&mut self, effects: &[post::PostEffect]) -> std::io::Result<()>;
+    fn surface(&mut self) -> Self::Surface;
+    /// an empty texture to draw into, in pixels. fails when the backend
+    /// can not make one that big
+    fn add_target(&mut self, width: u32, height: u32) -> std::io::Result<RenderTarget>;
+    /// draws into a target instead of the screen, the target can not be
+    /// used as a sprite by the queue filling it
+    fn target_surface(&mut self, target: &RenderTarget) -> Self::Surface;
+}
+
+/// an offscreen surface: fill it with its own queue, then draw it as a sprite
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RenderTarget {
+    pub texture: u32,
+    pub width: u32,
+    pub height: u32,
 }
 
 /// trait that hides the surface we draw to
 pub trait StreamLineBackendSurface {
     fn dimensions(&self) -> (f32, f32);
+    /// draw and clear only inside this area, None is the whole surface
+    fn set_viewport(&mut self, viewport: Option<Viewport>);
+    /// cut the primitives drawn from now on to this area, without scaling them
+    fn set_scissor(&mut self, scissor: Option<Viewport>);
+    /// how the primitives drawn from now on use the stencil buffer
+    fn set_mask(&mut self, mask: Option<Mask>);
+    fn set_blend(&mut self, mode: BlendMode);
+    /// sprites and rects drawn from now on use this material
+    fn set_material(&mut self, material: Option<MaterialId>);
+    /// the value used by the draws that follow with the material, the queue
+    /// sends the uniforms of a material with every batch using it
+    fn set_uniform(&mut self, material: MaterialId, name: &str, value: f32);
     fn clear(&mut self, color: &Color);
     fn draw_sprites(&mut self, sprites: &[SpriteLayout], tex: u32);
     fn draw_lines(&mut self, lines: &[LineLayout], width: u32);
@@ -182,18 +380,349 @@ pub trait StreamLineBackendSurface {
 }
 
 
+/// everything shared by the primitives of a draw call, primitives issued
+/// with a different state end up in a different batch
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct DrawState {
+    /// area of the surface primitives are cut to, None for the whole surface
+    pub clip: Option<Viewport>,
+    /// stencil mask the primitives write or are drawn inside of
+    pub mask: Option<Mask>,
+    pub blend: BlendMode,
+    /// sprites sample this texture instead of the atlas
+    pub texture: Option<u32>,
+    pub material: Option<MaterialId>,
+    /// the values set_uniform gave to the uniforms of the material when the
+    /// primitives were issued, 0 for the ones it was added with
+    pub uniforms: usize,
+}
+
+/// how a primitive is merged with what is behind it
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BlendMode {
+    /// translucent colors, the default
+    Alpha,
+    /// adds light, for particles and glows
+    Additive,
+    /// darkens, for shadows
+    Multiply,
+    /// lightens, the opposite of multiply
+    Screen,
+    /// like alpha, for colors already multiplied by their alpha
+    Premultiplied,
+}
+
+impl Default for BlendMode {
+    fn default() -> BlendMode {
+        BlendMode::Alpha
+    }
+}
+
+/// what primitives do with the mask
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MaskStage {
+    /// shapes are added to the mask, nothing is drawn
+    Write,
+    /// drawn only inside of the mask
+    Draw,
+    /// the shapes are removed again, nothing is drawn
+    Erase,
+}
+
+/// masks nest: a mask only covers what is inside of its parent
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Mask {
+    /// every mask is different, even with the same shapes
+    pub id: u32,
+    /// number of masks active, this one included
+    pub depth: u32,
+    pub stage: MaskStage,
+}
+
+/// in which order primitives reach the backend
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DrawOrder {
+    /// as few draw calls as possible, the depth test sorts the layers.
+    /// translucent primitives may hide what is behind them
+    Batched,
+    /// back to front by layer, and in the order they were issued within a
+    /// layer, like a painter would. consecutive primitives of the same batch
+    /// still share a draw call. masks are drawn in stages, so primitives
+    /// are only sorted with the others of the same stage
+    BackToFront,
+}
+
+// layouts of one kind, with the order they were issued in
+#[derive(Clone)]
+struct Layouts<T> {
+    list: Vec<T>,
+    order: Vec<u32>,
+}
+
+impl<T> Layouts<T> {
+    fn new() -> Layouts<T> {
+        Layouts {
+            list: Vec::new(),
+            order: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, layout: T, seq: u32) {
+        self.list.push(layout);
+        self.order.push(seq);
+    }
+
+    fn clear(&mut self) {
+        self.list.clear();
+        self.order.clear();
+    }
+
+    fn drain(&mut self, range: Range<usize>) -> Vec<(T, u32)> {
+        let order: Vec<u32> = self.order.drain(range.clone()).collect();
+        self.list.drain(range).zip(order).collect()
+    }
+}
+
+fn compare_keys(a: &(f32, u32), b: &(f32, u32)) -> std::cmp::Ordering {
+    a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal).then(a.1.cmp(&b.1))
+}
+
+impl<T> Deref for Layouts<T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        &self.list
+    }
+}
+
+impl<T> DerefMut for Layouts<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        &mut self.list
+    }
+}
+
+// primitives drawn with the same state
+struct Batch {
+    state: DrawState,
+    lines: Map<u32, Layouts<LineLayout>>,
+    sprites: Layouts<SpriteLayout>,
+    rects: Layouts<RectLayout>,
+    texts: Layouts<TextLayout>,
+}
+
+impl Batch {
+    fn new(state: DrawState) -> Batch {
+        Batch {
+            state: state,
+            lines: Map::new(),
+            sprites: Layouts::new(),
+            rects: Layouts::new(),
+            texts: Layouts::new(),
+        }
+    }
+
+    // empty, the lists keep their memory and the widths used
+    fn clear(&mut self) {
+        for lines in self.lines.values_mut() {
+            lines.clear();
+        }
+        self.sprites.clear();
+        self.rects.clear();
+        self.texts.clear();
+    }
+}
+
+// the batches of the frame, and the ones of previous frames to reuse
+struct Batches {
+    used: Vec<Batch>,
+    spare: Vec<Batch>,
+    // surface pixels per pixel of line width, the virtual resolution scales them
+    line_scale: f32,
+    // every set of values given with set_uniform in the frame, the first one
+    // is empty, and the one each material uses now
+    uniforms: Vec<Vec<(String, f32)>>,
+    materials: Map<MaterialId, usize>,
+}
+
+impl Batches {
+    fn new() -> Batches {
+        Batches {
+            used: Vec::new(),
+            spare: Vec::new(),
+            line_scale: 1.0,
+            uniforms: vec![Vec::new()],
+            materials: Map::new(),
+        }
+    }
+
+    fn uniforms_of(&self, material: MaterialId) -> usize {
+        self.materials.get(&material).cloned().unwrap_or(0)
+    }
+
+    // the batches issued before keep their values, a new set is only needed
+    // when some batch uses the current one. the first set is shared by all
+    fn set_uniform(&mut self, material: MaterialId, name: &str, value: f32) {
+        let current = self.uniforms_of(material);
+        if current == 0 || self.used.iter().any(|b| b.state.uniforms == current) {
+            let values = self.uniforms[current].clone();
+            self.uniforms.push(values);
+            self.materials.insert(material, self.uniforms.len() - 1);
+        }
+
+        let values = &mut self.uniforms[self.materials[&material]];
+        match values.iter().position(|v| v.0 == name) {
+            Some(i) => values[i].1 = value,
+            None => values.push((name.to_string(), value)),
+        }
+    }
+
+    // the width lines are drawn with, borders too
+    fn line_width(&self, width: u32) -> u32 {
+        ((width as f32 * self.line_scale).round() as u32).max(1)
+    }
+
+    // the batch for a state, a new one is added at the end if none has it
+    fn index(&mut self, state: DrawState) -> usize {
+        match self.used.iter().position(|b| b.state == state) {
+            Some(i) => i,
+            None => {
+                let batch = self.take(state);
+                self.used.push(batch);
+                self.used.len() - 1
+            }
+        }
+    }
+
+    fn take(&mut self, state: DrawState) -> Batch {
+        match self.spare.pop() {
+            Some(mut batch) => {
+                batch.state = state;
+                batch
+            }
+            None => Batch::new(state),
+        }
+    }
+
+    // adds an erase batch for every batch writing the mask, whatever the
+    // clip or the blending they were issued with
+    fn erase_mask(&mut self, id: u32) {
+        let writes: Vec<usize> = (0..self.used.len())
+            .filter(|i| match self.used[*i].state.mask {
+                Some(mask) => mask.id == id && mask.stage == MaskStage::Write,
+                None => false,
+            })
+            .collect();
+
+        for i in writes {
+            let from = self.used[i].state;
+            let mask = from.mask.map(|mask| Mask { stage: MaskStage::Erase, ..mask });
+            let mut batch = self.take(DrawState { mask: mask, ..from });
+            {
+                let src = &self.used[i];
+                batch.lines.clone_from(&src.lines);
+                batch.sprites.clone_from(&src.sprites);
+                batch.rects.clone_from(&src.rects);
+                batch.texts.clone_from(&src.texts);
+            }
+            self.used.push(batch);
+        }
+    }
+
+    // the next frame starts with the batches in the same order
+    fn reset(&mut self) {
+        for mut batch in self.used.drain(..).rev() {
+            batch.clear();
+            self.spare.push(batch);
+        }
+        self.uniforms.truncate(1);
+        self.materials.clear();
+    }
+}
+
+impl Deref for Batches {
+    type Target = Vec<Batch>;
+    fn deref(&self) -> &Vec<Batch> {
+        &self.used
+    }
+}
+
+impl DerefMut for Batches {
+    fn deref_mut(&mut self) -> &mut Vec<Batch> {
+        &mut self.used
+    }
+}
+
+// the uniforms of the material go with every batch, so each one is drawn
+// with the values it was issued with whatever was drawn before
+fn set_state<S: StreamLineBackendSurface>(surface: &mut S, state: &DrawState, batches: &Batches, assets: &AssetsMgr) {
+    surface.set_scissor(state.clip);
+    surface.set_mask(state.mask);
+    surface.set_blend(state.blend);
+    surface.set_material(state.material);
+    if let Some(material) = state.material {
+        let values = &batches.uniforms[state.uniforms];
+        for &(ref name, value) in assets.get_uniforms(material) {
+            let value = values.iter().find(|v| v.0 == *name).map_or(value, |v| v.1);
+            surface.set_uniform(material, name, value);
+        }
+    }
+}
+
+// one primitive, to be sorted back to front
+#[derive(Copy, Clone, PartialEq)]
+enum Kind {
+    Line(u32),
+    Sprite,
+    Rect,
+    Text,
+}
+
+struct Entry {
+    layer: f32,
+    seq: u32,
+    batch: usize,
+    kind: Kind,
+    index: usize,
+}
+
+impl Entry {
+    fn new(layer: f32, seq: u32, batch: usize, kind: Kind, index: usize) -> Entry {
+        Entry {
+            layer: layer,
+            seq: seq,
+            batch: batch,
+            kind: kind,
+            index: index,
+        }
+    }
+}
+
 /// The command queue is a transient object:
 /// we create it on each frame, then we fill it with the drawing instructions,
-/// and finally it is issued and discarded
+/// and finally it is issued and discarded.
+/// To keep the memory of its lists from one frame to the next, create it
+/// reusable instead, and begin every frame with it
 pub struct CmdQueue<'a, S>
     where S: StreamLineBackendSurface
 {
-    surface: S,
+    // only between begin and end of a frame
+    surface: Option<S>,
     assets: &'a AssetsMgr,
-    lines: RcRef<Map<u32, RcRef<Vec<LineLayout>>>>,
-    sprites: RcRef<Vec<SpriteLayout>>,
-    rects: RcRef<Vec<RectLayout>>,
-    texts: RcRef<Vec<TextLayout>>,
+    // in order of first use, primitives go to the current one
+    batches: Batches,
+    current: usize,
+    state: DrawState,
+    clips: Vec<Viewport>,
+    masks: Vec<u32>,
+    mask_count: u32,
+    // primitives issued so far
+    count: u32,
+    // primitives issued on layers without a depth of their own
+    clamped: u32,
+    order: DrawOrder,
+    camera: Option<Camera>,
+    transforms: Vec<Transform>,
+    origin: Origin,
+    resolution: Option<VirtualResolution>,
 }
 
 impl<'a, S> CmdQueue<'a, S>
@@ -201,167 +730,739 @@ impl<'a, S> CmdQueue<'a, S>
 {
     /// create a new queue
     pub fn new(surface: S, assets_mgr: &'a AssetsMgr) -> CmdQueue<'a, S> {
+        let mut q = CmdQueue::empty(assets_mgr);
+        q.begin_frame(surface);
+        q
+    }
 
+    /// a queue without surface, that will be used for many frames. once the
+    /// lists grew enough, frames issuing the same primitives allocate nothing
+    /// but the strings of texts
+    pub fn reusable(assets_mgr: &'a AssetsMgr) -> ReusableQueue<'a, S> {
+        ReusableQueue { queue: CmdQueue::empty(assets_mgr) }
+    }
+
+    fn empty(assets_mgr: &'a AssetsMgr) -> CmdQueue<'a, S> {
         CmdQueue {
-            surface: surface,
+            surface: None,
             assets: assets_mgr,
-            lines: RcRef::new(Map::new()),
-            sprites: RcRef::new(Vec::new()),
-            rects: RcRef::new(Vec::new()),
-            texts: RcRef::new(Vec::new()),
+            batches: Batches::new(),
+            current: 0,
+            state: DrawState::default(),
+            clips: Vec::new(),
+            masks: Vec::new(),
+            mask_count: 0,
+            count: 0,
+            clamped: 0,
+            order: DrawOrder::Batched,
+            camera: None,
+            transforms: Vec::new(),
+            origin: Origin::BottomLeft,
+            resolution: None,
+        }
+    }
+
+    // as a new queue would. a frame left pending, by a guard that was
+    // forgotten, is issued first
+    fn begin_frame(&mut self, surface: S) {
+        if self.surface.is_some() {
+            self.end_frame();
+        }
+        self.batches.reset();
+        self.current = self.batches.index(DrawState::default());
+        self.state = DrawState::default();
+        self.clips.clear();
+        self.masks.clear();
+        self.mask_count = 0;
+        self.count = 0;
+        self.clamped = 0;
+        self.order = DrawOrder::Batched;
+        self.camera = None;
+        self.transforms.clear();
+        self.origin = Origin::BottomLeft;
+        self.resolution = None;
+        self.batches.line_scale = 1.0;
+        self.surface = Some(surface);
+    }
+
+    fn surface(&self) -> &S {
+        self.surface.as_ref().expect("no frame begun")
+    }
+
+    // layouts replayed from a command list were checked when generated
+    fn check_layer(&mut self, layer: i32) {
+        if !layer_in_range(layer) {
+            self.clamped += 1;
         }
     }
 
+    /// primitives issued this frame on layers out of range, they share the
+    /// depth of the nearest end and may be drawn in any order between them
+    pub fn clamped_layers(&self) -> u32 {
+        self.clamped
+    }
+
+    fn surface_mut(&mut self) -> &mut S {
+        self.surface.as_mut().expect("no frame begun")
+    }
+
     /// clear the current canvas, overwriting anything done before
     pub fn clear(&mut self, color: &Color) {
-        self.surface.clear(color);
+        if let Some(res) = self.resolution {
+            self.surface_mut().set_viewport(None);
+            self.surface_mut().clear(&res.bars);
+            let vp = res.viewport(self.surface().dimensions());
+            self.surface_mut().set_viewport(Some(vp));
+        }
+        self.surface_mut().clear(color);
     }
 
+    /// issue everything in a virtual screen of fixed size, scaled to fit the
+    /// surface. set it before clearing, the bars are painted by clear
+    pub fn set_virtual_resolution(&mut self, resolution: Option<VirtualResolution>) {
+        self.resolution = resolution;
+        let dim = self.surface().dimensions();
+        self.batches.line_scale = resolution.map_or(1.0, |res| res.scale(dim));
+        self.surface_mut().set_viewport(resolution.map(|res| res.viewport(dim)));
+    }
 
-    /// draw a line between two points
-    #[cfg_attr(feature="profile", flame)]
-    pub fn line(&mut self, src: Vec2, dst: Vec2, width: u32, layer: u32) -> LayoutTune<LineLayout> {
+    /// size of the screen primitives are issued in, the virtual one if any
+    pub fn dimensions(&self) -> (f32, f32) {
+        match self.resolution {
+            Some(res) => (res.width as f32, res.height as f32),
+            None => self.surface().dimensions(),
+        }
+    }
+
+    /// choose where the pixel (0, 0) is, bottom left by default.
+    /// sizes are always in pixels and sprites are never upside down
+    pub fn set_origin(&mut self, origin: Origin) {
+        self.origin = origin;
+    }
+
+    /// issue primitives in world coordinates, seen through a camera.
+    /// the camera applies after every pushed transform
+    pub fn set_camera(&mut self, camera: Option<Camera>) {
+        self.camera = camera;
+    }
+
+    /// screen pixels to the coordinates primitives are issued in now, none
+    /// if a transform or the camera scales to nothing, or the window is
+    /// too small to show the virtual screen
+    pub fn screen_to_world(&self, p: Vec2) -> Option<Vec2> {
+        let p = match self.resolution {
+            Some(res) => {
+                let dim = self.surface().dimensions();
+                match self.origin {
+                    Origin::BottomLeft => res.to_virtual(p, dim),
+                    Origin::TopLeft => {
+                        res.to_virtual(Vec2::new(p.x, dim.1 - p.y), dim)
+                            .map(|v| Vec2::new(v.x, res.height as f32 - v.y))
+                    }
+                }
+            }
+            None => Some(p),
+        };
+        p.and_then(|p| self.transform().inverse().map(|t| t.apply(p)))
+    }
+
+    /// from now on, primitives are cut to this rectangle, and to the previous
+    /// clip if any. the clip is always axis aligned in the surface: when the
+    /// transform rotates, the box around the rectangle is used
+    pub fn push_clip(&mut self, position: Vec2, dimensions: Vec2) {
+        let mut area = self.surface_area(position, dimensions);
+        if let Some(top) = self.clips.last() {
+            area = top.intersect(&area);
+        }
+        self.clips.push(area);
+        self.state.clip = Some(area);
+        self.switch_batch();
+    }
 
-        // if we do not have a list for this width we need to create one
-        self.add_width_list(width);
+    /// restores the clip active before the last push
+    pub fn pop_clip(&mut self) {
+        self.clips.pop().expect("pop_clip without push_clip");
+        self.state.clip = self.clips.last().cloned();
+        self.switch_batch();
+    }
+
+    /// primitives issued from now on, until end_mask, are not drawn but
+    /// make a mask. transparent pixels of sprites are not part of it
+    pub fn begin_mask(&mut self) {
+        let id = self.mask_count;
+        self.mask_count += 1;
+        self.masks.push(id);
+        self.state.mask = Some(Mask {
+            id: id,
+            depth: self.masks.len() as u32,
+            stage: MaskStage::Write,
+        });
+        self.switch_batch();
+    }
+
+    /// primitives issued from now on, until pop_mask, are only drawn
+    /// inside of the mask
+    pub fn end_mask(&mut self) {
+        match self.state.mask {
+            Some(ref mut mask) if mask.stage == MaskStage::Write => mask.stage = MaskStage::Draw,
+            _ => panic!("end_mask without begin_mask"),
+        }
+        self.switch_batch();
+    }
+
+    /// stops using the last mask, back to the previous one if any
+    pub fn pop_mask(&mut self) {
+        let mask = match self.state.mask {
+            Some(mask) if mask.stage == MaskStage::Draw => mask,
+            _ => panic!("pop_mask without end_mask"),
+        };
+
+        // the shapes are drawn again to take them out of the stencil
+        self.batches.erase_mask(mask.id);
+
+        self.masks.pop();
+        self.state.mask = self.masks.last().map(|id| {
+            Mask {
+                id: *id,
+                depth: self.masks.len() as u32,
+                stage: MaskStage::Draw,
+            }
+        });
+        self.switch_batch();
+    }
 
-        let dim = self.surface.dimensions();
+    // primitives issued from now on go to the batch of the current state
+    fn switch_batch(&mut self) {
+        self.current = self.batches.index(self.state);
+    }
+
+    fn batch(&self) -> &Batch {
+        &self.batches[self.current]
+    }
+
+    fn batch_mut(&mut self) -> &mut Batch {
+        &mut self.batches[self.current]
+    }
+
+    // the box around a rectangle, in surface pixels
+    fn surface_area(&self, position: Vec2, dimensions: Vec2) -> Viewport {
+        let dim = self.surface().dimensions();
+        let vp = match self.resolution {
+            Some(res) => res.viewport(dim),
+            None => {
+                Viewport {
+                    left: 0,
+                    bottom: 0,
+                    width: dim.0 as u32,
+                    height: dim.1 as u32,
+                }
+            }
+        };
 
-        let lines_map_next = self.lines.clone();
-        let mut lines_map_rc = self.lines.get_mut();
-        let lines_list_rc = lines_map_rc.get_mut(&width).unwrap();
+        let (px, py) = self.position(position);
+        let (ux, uy) = self.vector(Vec2 { x: dimensions.x, y: 0.0 });
+        let (vx, vy) = self.vector(Vec2 { x: 0.0, y: dimensions.y });
+        let xs = [px, px + ux, px + vx, px + ux + vx];
+        let ys = [py, py + uy, py + vy, py + uy + vy];
+        let min = |v: &[f32]| v.iter().fold(std::f32::INFINITY, |a, b| a.min(*b));
+        let max = |v: &[f32]| v.iter().fold(std::f32::NEG_INFINITY, |a, b| a.max(*b));
+
+        // device coordinates to pixels, inside of the surface
+        let to_x = |x: f32| {
+            (vp.left as f32 + (x + 1.0) / 2.0 * vp.width as f32).round().max(0.0).min(dim.0)
+        };
+        let to_y = |y: f32| {
+            (vp.bottom as f32 + (y + 1.0) / 2.0 * vp.height as f32).round().max(0.0).min(dim.1)
+        };
+        let (left, right) = (to_x(min(&xs)), to_x(max(&xs)));
+        let (bottom, top) = (to_y(min(&ys)), to_y(max(&ys)));
+        Viewport {
+            left: left as u32,
+            bottom: bottom as u32,
+            width: (right - left) as u32,
+            height: (top - bottom) as u32,
+        }
+    }
 
-        let i = lines_list_rc.get().len();
+    /// from now on, every position is transformed by t before the current transform,
+    /// so primitives can be issued in local coordinates
+    pub fn push_transform(&mut self, t: Transform) {
+        let top = self.local_transform().then(&t);
+        self.transforms.push(top);
+    }
 
-        let lines_list_next = lines_list_rc.clone();
-        let mut list = lines_list_rc.get_mut();
+    /// restores the transform active before the last push
+    pub fn pop_transform(&mut self) {
+        self.transforms.pop().expect("pop_transform without push_transform");
+    }
+
+    /// the transform applied to the primitives issued now, camera included
+    pub fn transform(&self) -> Transform {
+        match self.camera {
+            Some(ref camera) => camera.view(self.dimensions()).then(&self.local_transform()),
+            None => self.local_transform(),
+        }
+    }
+
+    fn local_transform(&self) -> Transform {
+        *self.transforms.last().unwrap_or(&Transform::identity())
+    }
+
+    // pixel position to device coordinates, after the current transform
+    fn position(&self, p: Vec2) -> (f32, f32) {
+        let dim = self.dimensions();
+        let mut p = self.transform().apply(p);
+        if self.resolution.map_or(false, |res| res.pixel_perfect) {
+            p = Vec2::new(p.x.round(), p.y.round());
+        }
+        let y = match self.origin {
+            Origin::BottomLeft => p.y,
+            Origin::TopLeft => dim.1 - p.y,
+        };
+        ((p.x / (dim.0 / 2.0)) - 1.0, (y / (dim.1 / 2.0)) - 1.0)
+    }
+
+    // pixel distance to device coordinates, after the current transform
+    fn vector(&self, v: Vec2) -> (f32, f32) {
+        let dim = self.dimensions();
+        let v = self.transform().apply_vector(v);
+        let y = match self.origin {
+            Origin::BottomLeft => v.y,
+            Origin::TopLeft => -v.y,
+        };
+        (v.x / (dim.0 / 2.0), y / (dim.1 / 2.0))
+    }
+
+    // quad for an image spanning from (x, y) to (x + w, y + h) in pixels.
+    // the v edge must go from the bottom to the top of the image,
+    // whatever way y grows, or it would be drawn upside down
+    fn image_quad(&self, x: f32, y: f32, w: f32, h: f32) -> (f32, f32, f32, f32, f32, f32) {
+        let (bottom, up) = match self.origin {
+            Origin::BottomLeft => (y, h),
+            Origin::TopLeft => (y + h, -h),
+        };
+        let (px, py) = self.position(Vec2 { x: x, y: bottom });
+        let (ux, uy) = self.vector(Vec2 { x: w, y: 0.0 });
+        let (vx, vy) = self.vector(Vec2 { x: 0.0, y: up });
+        (px, py, ux, uy, vx, vy)
+    }
+
+
+    /// draw a line between two points
+    #[cfg_attr(feature="profile", flame)]
+    pub fn line(&mut self, src: Vec2, dst: Vec2, width: u32, layer: i32) -> LayoutTune<LineLayout> {
+        self.check_layer(layer);
+
+        let width = self.batches.line_width(width);
+        let (x1, y1) = self.position(src);
+        let (x2, y2) = self.position(dst);
+
+        let count = self.count;
+        self.count += 1;
+        let list = LineLayout::layouts(&mut self.batches[self.current], width);
+        let i = list.len();
         list.push(LineLayout([layer as f32 * 1.0,
-                              (src.x as f32 / (dim.0 / 2.0)) - 1.0,
-                              (src.y as f32 / (dim.1 / 2.0)) - 1.0,
-                              (dst.x as f32 / (dim.0 / 2.0)) - 1.0,
-                              (dst.y as f32 / (dim.1 / 2.0)) - 1.0,
+                              x1,
+                              y1,
+                              x2,
+                              y2,
                               1.0,
                               1.0,
                               1.0,
-                              1.0]));
+                              1.0]),
+                  count);
 
-        LayoutTune {
-            last: i..i + 1,
-            lastqueue: lines_list_next,
-
-            dimensions: dim,
-            lines: lines_map_next,
-            _sprites: self.sprites.clone(),
-            _rects: self.rects.clone(),
-        }
+        LayoutTune::new(&mut self.batches, self.current, width, i..i + 1)
     }
 
     /// draw a sprite in a given location
     #[cfg_attr(feature="profile", flame)]
-    pub fn sprite(&mut self, pos: Vec2, layer: u32, sprite: SpriteId) -> LayoutTune<SpriteLayout> {
+    pub fn sprite(&mut self, pos: Vec2, layer: i32, sprite: SpriteId) -> LayoutTune<SpriteLayout> {
+        self.check_layer(layer);
 
-        let dim = self.surface.dimensions();
-        let ratio = dim.1 / dim.0;
         let (x, y) = self.assets.get_sprite_offset(sprite).unwrap();
         let (w, h) = self.assets.get_sprite_size(sprite).unwrap();
 
-        let i = self.sprites.get().len();
-        self.sprites
-            .get_mut()
-            .push(SpriteLayout([layer as f32,
-                                (pos.x as f32 / (dim.0 / 2.0)) - 1.0,
-                                (pos.y as f32 / (dim.1 / 2.0)) - 1.0,
-                                w * ratio,
-                                h,
-                                x,
-                                y,
-                                w,
-                                h]));
+        // sprites keep the size in pixels of their image
+        let atlas = self.assets.get_atlas_size();
+        let (px, py, ux, uy, vx, vy) = self.image_quad(pos.x, pos.y, w * atlas.0, h * atlas.1);
 
-        LayoutTune {
-            last: i..i + 1,
-            lastqueue: self.sprites.clone(),
+        self.push_sprite(SpriteLayout([layer as f32, px, py, ux, uy, vx, vy, x, y, w, h, 1.0, 1.0, 1.0, 1.0]))
+    }
 
-            dimensions: dim,
-            lines: self.lines.clone(),
-            _sprites: self.sprites.clone(),
-            _rects: self.rects.clone(),
-        }
+    /// draw the content of a render target, at its size in pixels
+    pub fn target_sprite(&mut self,
+                         pos: Vec2,
+                         layer: i32,
+                         target: &RenderTarget)
+                         -> LayoutTune<SpriteLayout> {
+        self.check_layer(layer);
+
+        let (w, h) = (target.width as f32, target.height as f32);
+        let (px, py, ux, uy, vx, vy) = self.image_quad(pos.x, pos.y, w, h);
+
+        // goes in a batch of its own, sampling the target
+        let state = self.state;
+        self.state.texture = Some(target.texture);
+        self.switch_batch();
+        let batch = self.current;
+        self.push_sprite(SpriteLayout([layer as f32,
+                                                  px,
+                                                  py,
+                                                  ux,
+                                                  uy,
+                                                  vx,
+                                                  vy,
+                                                  0.0,
+                                                  1.0,
+                                                  1.0,
+                                                  1.0,
+                                                  1.0,
+                                                  1.0,
+                                                  1.0,
+                                                  1.0]));
+        self.state = state;
+        self.switch_batch();
+
+        let i = self.batches[batch].sprites.len() - 1;
+        LayoutTune::new(&mut self.batches, batch, 0, i..i + 1)
+    }
+
+    fn push_sprite(&mut self, sprite: SpriteLayout) -> LayoutTune<SpriteLayout> {
+
+        let i = self.batch().sprites.len();
+        self.batches[self.current].sprites.push(sprite, self.count);
+        self.count += 1;
+
+        LayoutTune::new(&mut self.batches, self.current, 0, i..i + 1)
     }
 
     /// draw a rectangle
     #[cfg_attr(feature="profile", flame)]
-    pub fn rect(&mut self, position: Vec2, dimensions: Vec2, layer: u32) -> LayoutTune<RectLayout> {
-
-        let dim = self.surface.dimensions();
-        let ratio = dim.1 / dim.0;
-
-        let i = self.rects.get().len();
-        self.rects
-            .get_mut()
-            .push(RectLayout([layer as f32,
-                              (position.x as f32 / (dim.0 / 2.0)) - 1.0,
-                              (position.y as f32 / (dim.1 / 2.0)) - 1.0,
-                              (dimensions.x as f32 / (dim.1 / 2.0)), // * ratio,
-                              (dimensions.y as f32 / (dim.1 / 2.0)) * ratio,
-                              0.0,
-                              0.0,
-                              0.0,
-                              1.0]));
+    pub fn rect(&mut self, position: Vec2, dimensions: Vec2, layer: i32) -> LayoutTune<RectLayout> {
+        self.check_layer(layer);
 
-        LayoutTune {
-            last: i..i + 1,
-            lastqueue: self.rects.clone(),
+        let (px, py) = self.position(position);
+        let (ux, uy) = self.vector(Vec2 { x: dimensions.x, y: 0.0 });
+        let (vx, vy) = self.vector(Vec2 { x: 0.0, y: dimensions.y });
 
-            dimensions: dim,
-            lines: self.lines.clone(),
-            _sprites: self.sprites.clone(),
-            _rects: self.rects.clone(),
-        }
+        let i = self.batch().rects.len();
+        self.batches[self.current]
+            .rects
+            .push(RectLayout([layer as f32, px, py, ux, uy, vx, vy, 0.0, 0.0, 0.0, 1.0]),
+                  self.count);
+        self.count += 1;
+
+        LayoutTune::new(&mut self.batches, self.current, 0, i..i + 1)
     }
 
-    /// draws text
-    pub fn text(&mut self, pos: Vec2, layer: u32, font: FontId, txt: &str) {
-        let mut list = self.texts.get_mut();
-        let dim = self.surface.dimensions();
-        list.push(TextLayout {
+    /// draws text, the transform only moves the origin of vector fonts
+    pub fn text(&mut self, pos: Vec2, layer: i32, font: FontId, txt: &str) {
+        self.check_layer(layer);
+
+        // bitmap fonts are just sprites from the atlas
+        if let Some(bitmap) = self.assets.get_bitmap_font(&font) {
+            let mut glyphs = Vec::new();
+            let mut cursor = pos.x;
+            let mut prev = None;
+            for c in txt.chars() {
+                let glyph = match bitmap.get_char(c) {
+                    Some(glyph) => glyph,
+                    None => continue,
+                };
+                if let Some(prev) = prev {
+                    cursor += bitmap.kerning(prev, c) as f32;
+                }
+                prev = Some(c);
+
+                // pos is the base line, glyphs are placed from the top of the line
+                let x = cursor + glyph.xoffset as f32;
+                let (w, h) = (glyph.width as f32, glyph.height as f32);
+                let y = match self.origin {
+                    Origin::BottomLeft => {
+                        pos.y + bitmap.base() as f32 - glyph.yoffset as f32 - h
+                    }
+                    Origin::TopLeft => pos.y - bitmap.base() as f32 + glyph.yoffset as f32,
+                };
+                let (tx, ty, tw, th) = glyph.tex;
+                let (px, py, ux, uy, vx, vy) = self.image_quad(x, y, w, h);
+                glyphs.push(SpriteLayout([layer as f32, px, py, ux, uy, vx, vy, tx, ty, tw, th,
+                                          1.0, 1.0, 1.0, 1.0]));
+                cursor += glyph.xadvance as f32;
+            }
+            let count = self.count;
+            let sprites = &mut self.batch_mut().sprites;
+            for glyph in glyphs {
+                sprites.push(glyph, count);
+            }
+            self.count += 1;
+            return;
+        }
+
+        let pos = self.position(pos);
+        let layout = TextLayout {
             layer: layer as f32,
-            pos: ((pos.x as f32 / (dim.0 / 2.0)) - 1.0, (pos.y as f32 / (dim.1 / 2.0)) - 1.0),
+            pos: pos,
             color: (1.0, 1.0, 1.0, 1.0),
-            font: self.assets.get_font(&font),
+            font: self.assets.get_font(&font).expect("font not loaded"),
             text: txt.to_string(),
-        });
+        };
+        self.batches[self.current].texts.push(layout, self.count);
+        self.count += 1;
+    }
+
+    /// changes a uniform of a material for the primitives issued from now on,
+    /// the ones issued before keep their value. every frame starts with the
+    /// values the material was added with
+    pub fn set_uniform(&mut self, material: MaterialId, name: &str, value: f32) {
+        self.batches.set_uniform(material, name, value);
+    }
+
+    /// batched by default, see DrawOrder
+    pub fn set_draw_order(&mut self, order: DrawOrder) {
+        self.order = order;
     }
 
     /// finishes and consummes the queue, issues all the draw calls to the backend
     pub fn done(mut self) {
+        self.end_frame();
+    }
+
+    // issues all the draw calls of the frame to the backend, the queue
+    // waits for the next begin_frame
+    fn end_frame(&mut self) {
+        let mut surface = self.surface.take().expect("no frame begun");
+
+        if self.clamped > 0 {
+            eprintln!("warning: {} primitives on layers out of {}..{} share the depth of the nearest end",
+                      self.clamped, -((LAYERS / 2) as i32), LAYERS / 2 - 1);
+        }
+
+        if self.order == DrawOrder::BackToFront {
+            self.draw_back_to_front(&mut surface);
+            return surface.done();
+        }
+
+        let atlas = self.assets.get_atlas();
+        for batch in self.batches.iter() {
+            set_state(&mut surface, &batch.state, &self.batches, self.assets);
+
+            // get all lines, orderer by depth and then width
+            for (width, line) in batch.lines.iter() {
+                if !line.is_empty() {
+                    surface.draw_lines(line, *width);
+                }
+            }
+            // get all sprites,
+            if !batch.sprites.is_empty() {
+                let tex = batch.state.texture.unwrap_or(atlas);
+                surface.draw_sprites(&batch.sprites, tex);
+            }
+            // rectagles
+            if !batch.rects.is_empty() {
+                surface.draw_rects(&batch.rects);
+            }
+            // text
+            if !batch.texts.is_empty() {
+                surface.draw_texts(&batch.texts);
+            }
+        }
+
+        surface.done()
+    }
+
+    fn draw_back_to_front(&self, surface: &mut S) {
+        let batches = &self.batches;
+        let atlas = self.assets.get_atlas();
 
-        // get all lines, orderer by depth and then width
-        for (width, line) in self.lines.get().iter() {
-            // let v = self.lines.get(layer).unwrap();
-            self.surface.draw_lines(line.get().as_slice(), *width);
+        // batches of the same mask stage, in the order they were created
+        let mut stages: Vec<(Option<Mask>, Vec<usize>)> = Vec::new();
+        for (b, batch) in batches.iter().enumerate() {
+            match stages.iter().position(|s| s.0 == batch.state.mask) {
+                Some(i) => stages[i].1.push(b),
+                None => stages.push((batch.state.mask, vec![b])),
+            }
         }
-        // get all sprites,
-        self.surface
-            .draw_sprites(self.sprites.get().as_slice(), self.assets.get_atlas());
-        // rectagles
-        self.surface.draw_rects(self.rects.get().as_slice());
 
-        // text
-        self.surface.draw_texts(self.texts.get().as_slice());
+        for &(_, ref members) in stages.iter() {
+            let mut entries = Vec::new();
+            for &b in members.iter() {
+                let batch = &batches[b];
+                for (width, lines) in batch.lines.iter() {
+                    for (i, l) in lines.iter().enumerate() {
+                        entries.push(Entry::new(l.0[0], lines.order[i], b, Kind::Line(*width), i));
+                    }
+                }
+                let sprites = &batch.sprites;
+                for (i, l) in sprites.iter().enumerate() {
+                    entries.push(Entry::new(l.0[0], sprites.order[i], b, Kind::Sprite, i));
+                }
+                let rects = &batch.rects;
+                for (i, l) in rects.iter().enumerate() {
+                    entries.push(Entry::new(l.0[0], rects.order[i], b, Kind::Rect, i));
+                }
+                let texts = &batch.texts;
+                for (i, l) in texts.iter().enumerate() {
+                    entries.push(Entry::new(l.layer, texts.order[i], b, Kind::Text, i));
+                }
+            }
 
-        self.surface.done()
+            // lower layers are further away
+            entries.sort_by(|a, b| compare_keys(&(a.layer, a.seq), &(b.layer, b.seq)));
+
+            // consecutive primitives of the same kind and batch go together
+            let mut start = 0;
+            while start < entries.len() {
+                let (b, kind) = (entries[start].batch, entries[start].kind);
+                let end = entries[start..]
+                    .iter()
+                    .position(|e| e.batch != b || e.kind != kind)
+                    .map_or(entries.len(), |n| start + n);
+
+                let batch = &batches[b];
+                let run = entries[start..end].iter().map(|e| e.index);
+                set_state(surface, &batch.state, batches, self.assets);
+                match kind {
+                    Kind::Line(width) => {
+                        let list = &batch.lines[&width];
+                        let run: Vec<_> = run.map(|i| list[i]).collect();
+                        surface.draw_lines(&run, width);
+                    }
+                    Kind::Sprite => {
+                        let list = &batch.sprites;
+                        let run: Vec<_> = run.map(|i| list[i]).collect();
+                        surface.draw_sprites(&run, batch.state.texture.unwrap_or(atlas));
+                    }
+                    Kind::Rect => {
+                        let list = &batch.rects;
+                        let run: Vec<_> = run.map(|i| list[i]).collect();
+                        surface.draw_rects(&run);
+                    }
+                    Kind::Text => {
+                        let list = &batch.texts;
+                        let run: Vec<_> = run.map(|i| list[i].clone()).collect();
+                        surface.draw_texts(&run);
+                    }
+                }
+                start = end;
+            }
+        }
     }
 
 
-    // if we do not have a list for this width we need to create one
-    fn add_width_list(&mut self, width: u32) {
+    // issues one recorded primitive, with the tunes it was recorded with
+    fn issue(&mut self,
+             command: &Command,
+             tint: Color,
+             blend: Option<BlendMode>,
+             material: Option<MaterialId>) {
+        // the command goes straight to the batch of its state, lines have no material
+        let state = self.state;
+        if let Some(blend) = blend {
+            self.state.blend = blend;
+        }
+        match (material, command) {
+            (_, &Command::Line { .. }) | (None, _) => {}
+            (Some(material), _) => {
+                self.state.material = Some(material);
+                self.state.uniforms = self.batches.uniforms_of(material);
+            }
+        }
+        if self.state != state {
+            self.switch_batch();
+        }
+
+        match *command {
+            Command::Line { src, dst, width, layer, color } => {
+                let c = tinted(color, tint);
+                self.line(src, dst, width, layer).with_color(c[0], c[1], c[2], c[3]);
+            }
+            Command::Sprite { pos, layer, sprite } => {
+                self.sprite(pos, layer, sprite).with_color(tint[0], tint[1], tint[2], tint[3]);
+            }
+            Command::Rect { pos, size, layer, color } => {
+                let c = tinted(color, tint);
+                self.rect(pos, size, layer).with_color(c[0], c[1], c[2], c[3]);
+            }
+            Command::Text { pos, layer, font, ref text } => {
+                let (sprites, texts) = (self.batch().sprites.len(), self.batch().texts.len());
+                self.text(pos, layer, font, text);
+
+                // bitmap fonts end up in the sprites, vector ones in the texts
+                let batch = self.batch_mut();
+                for &mut SpriteLayout(ref mut l) in &mut batch.sprites[sprites..] {
+                    let c = tinted([l[11], l[12], l[13], l[14]], tint);
+                    l[11..15].copy_from_slice(&c);
+                }
+                for layout in &mut batch.texts[texts..] {
+                    let (r, g, b, a) = layout.color;
+                    let c = tinted([r, g, b, a], tint);
+                    layout.color = (c[0], c[1], c[2], c[3]);
+                }
+            }
+        }
 
-        if self.lines.get().get(&width).is_none() {
-            self.lines.get_mut().insert(width, RcRef::new(Vec::new()));
+        if self.state != state {
+            self.state = state;
+            self.switch_batch();
         }
     }
 }
+fn tinted(c: Color, tint: Color) -> Color {
+    [c[0] * tint[0], c[1] * tint[1], c[2] * tint[2], c[3] * tint[3]]
+}
+
+/// a queue kept from frame to frame, see CmdQueue::reusable. it issues
+/// nothing by itself, every frame is drawn with the guard of begin_frame
+pub struct ReusableQueue<'a, S>
+    where S: StreamLineBackendSurface
+{
+    queue: CmdQueue<'a, S>,
+}
+
+impl<'a, S> ReusableQueue<'a, S>
+    where S: StreamLineBackendSurface
+{
+    /// starts a frame on the surface, as a new queue would
+    pub fn begin_frame<'q>(&'q mut self, surface: S) -> FrameGuard<'q, 'a, S> {
+        self.queue.begin_frame(surface);
+        FrameGuard { queue: &mut self.queue }
+    }
+}
+
+/// the queue of a frame begun on a reusable queue, the draw calls are
+/// issued to the backend when it is dropped
+pub struct FrameGuard<'q, 'a: 'q, S>
+    where S: StreamLineBackendSurface + 'q
+{
+    queue: &'q mut CmdQueue<'a, S>,
+}
+
+impl<'q, 'a: 'q, S> Deref for FrameGuard<'q, 'a, S>
+    where S: StreamLineBackendSurface + 'q
+{
+    type Target = CmdQueue<'a, S>;
+    fn deref(&self) -> &CmdQueue<'a, S> {
+        self.queue
+    }
+}
+
+impl<'q, 'a: 'q, S> DerefMut for FrameGuard<'q, 'a, S>
+    where S: StreamLineBackendSurface + 'q
+{
+    fn deref_mut(&mut self) -> &mut CmdQueue<'a, S> {
+        self.queue
+    }
+}
+
+impl<'q, 'a: 'q, S> Drop for FrameGuard<'q, 'a, S>
+    where S: StreamLineBackendSurface + 'q
+{
+    fn drop(&mut self) {
+        self.queue.end_frame();
+    }
+}
+
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
 use tools::LayoutHash;
@@ -398,6 +1499,26 @@ impl LayoutHash for RectLayout {
     }
 }
 
+impl LayoutHash for TextLayout {
+    fn hash<H: Hasher>(&self, h: &mut H) {
+        let values = [self.layer,
+                      self.pos.0,
+                      self.pos.1,
+                      self.color.0,
+                      self.color.1,
+                      self.color.2,
+                      self.color.3];
+        for i in &values {
+            let v = unsafe { transmute::<f32, u32>(*i) };
+            h.write_u32(v);
+        }
+        h.write_u32(self.font);
+        // length first, so consecutive strings can not be confused
+        h.write_usize(self.text.len());
+        h.write(self.text.as_bytes());
+    }
+}
+
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 #[cfg(test)]
@@ -414,6 +1535,14 @@ mod tests {
     use super::LineLayout;
     use super::RectLayout;
     use super::TextLayout;
+    use super::VectorFont;
+    use super::Viewport;
+    use super::Mask;
+    use super::BlendMode;
+    use super::RenderTarget;
+    use super::Material;
+    use super::MaterialId;
+    use super::post::PostEffect;
 
     use super::Colorize;
 
@@ -422,24 +1551,51 @@ mod tests {
 
     use std::io;
 
-    struct TestBE;
+    // shared with the tests of the other modules
+    #[derive(Default)]
+    pub struct TestBE {
+        materials: MaterialId,
+    }
     impl StreamLineBackend for TestBE {
         type Surface = TestBESurface;
         fn add_texture(&mut self, _img: RgbaImage) -> u32 {
             0
         }
-        fn add_font<FIO: io::Read>(&mut self, _font: FIO) -> u32 {
-            0
+        fn add_font(&mut self, _font: VectorFont) -> io::Result<u32> {
+            Ok(0)
         }
-        fn surface(&mut self, _: u32) -> Self::Surface {
+        fn add_material(&mut self, _material: &Material) -> io::Result<MaterialId> {
+            self.materials += 1;
+            Ok(self.materials - 1)
+        }
+        fn set_post_effects(&mut self, _effects: &[PostEffect]) -> io::Result<()> {
+            Ok(())
+        }
+        fn surface(&mut self) -> Self::Surface {
+            TestBESurface {}
+        }
+        fn add_target(&mut self, width: u32, height: u32) -> io::Result<RenderTarget> {
+            Ok(RenderTarget {
+                texture: 1,
+                width: width,
+                height: height,
+            })
+        }
+        fn target_surface(&mut self, _target: &RenderTarget) -> Self::Surface {
             TestBESurface {}
         }
     }
-    struct TestBESurface;
+    pub struct TestBESurface;
     impl StreamLineBackendSurface for TestBESurface {
         fn dimensions(&self) -> (f32, f32) {
-            (0.0, 0.0)
+            (800.0, 600.0)
         }
+        fn set_viewport(&mut self, _viewport: Option<Viewport>) {}
+        fn set_scissor(&mut self, _scissor: Option<Viewport>) {}
+        fn set_mask(&mut self, _mask: Option<Mask>) {}
+        fn set_blend(&mut self, _mode: BlendMode) {}
+        fn set_material(&mut self, _material: Option<MaterialId>) {}
+        fn set_uniform(&mut self, _material: MaterialId, _name: &str, _value: f32) {}
         fn clear(&mut self, _color: &Color) {}
         fn draw_sprites(&mut self, _sprites: &[SpriteLayout], _tex: u32) {}
         fn draw_lines(&mut self, _lines: &[LineLayout], _w: u32) {}
@@ -448,10 +1604,637 @@ mod tests {
         fn done(self) {}
     }
 
+    #[test]
+    fn transform_stack() {
+        use super::maths::Transform;
+        use std::f32::consts::PI;
+
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+        let mut q = CmdQueue::new(be.surface(), &ass);
+
+        q.push_transform(Transform::translate(400.0, 300.0));
+        q.rect(vec2(0, 0), vec2(40, 30), 0);
+        q.push_transform(Transform::rotate(PI / 2.0));
+        q.rect(vec2(0, 0), vec2(40, 30), 0);
+        q.pop_transform();
+        q.pop_transform();
+        q.rect(vec2(0, 0), vec2(40, 30), 0);
+
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-5;
+        let rects = &q.batch().rects;
+
+        // moved to the center
+        let RectLayout(r) = rects[0];
+        assert!(close(r[1], 0.0) && close(r[2], 0.0));
+        assert!(close(r[3], 0.1) && close(r[4], 0.0));
+        assert!(close(r[5], 0.0) && close(r[6], 0.1));
+
+        // and rotated, width now grows upwards and height to the left
+        let RectLayout(r) = rects[1];
+        assert!(close(r[1], 0.0) && close(r[2], 0.0));
+        assert!(close(r[3], 0.0) && close(r[4], 40.0 / 300.0));
+        assert!(close(r[5], -30.0 / 400.0) && close(r[6], 0.0));
+
+        // back to the origin
+        let RectLayout(r) = rects[2];
+        assert!(close(r[1], -1.0) && close(r[2], -1.0));
+    }
+
+    #[test]
+    fn camera() {
+        use super::camera::Camera;
+        use super::maths::{Transform, Vec2};
+
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+        let mut q = CmdQueue::new(be.surface(), &ass);
+
+        let mut cam = Camera::new(Vec2::new(-500.0, -500.0));
+        cam.zoom = 2.0;
+        q.set_camera(Some(cam));
+        q.push_transform(Transform::translate(10.0, 0.0));
+        q.line(Vec2::new(-510.0, -500.0), Vec2::new(-500.0, -500.0), 1, 0);
+
+        // back to the local coordinates of the end of the line
+        let local = q.screen_to_world(Vec2::new(420.0, 300.0)).unwrap();
+        assert!((local.x + 500.0).abs() < 1e-3 && (local.y + 500.0).abs() < 1e-3);
+        q.push_transform(Transform::scale(0.0, 1.0));
+        assert!(q.screen_to_world(Vec2::new(420.0, 300.0)).is_none());
+        q.pop_transform();
+
+        let lines = &q.batch().lines;
+        let LineLayout(l) = lines.get(&1).unwrap()[0];
+        let expected = [0.0, 0.0, 20.0 / 400.0, 0.0];
+        for (a, b) in l[1..5].iter().zip(expected.iter()) {
+            assert!((a - b).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn origin() {
+        use super::maths::Origin;
+        use find_folder::Search;
+        use std::path::Path;
+
+        let mut file_location = Search::Parents(3)
+            .for_folder("assets")
+            .expect("some assets folder must exist somewhere");
+        file_location.push(Path::new("rust-logo.png"));
+
+        let mut be = TestBE::default();
+        let (ass, sp) = {
+            let mut builder = AssetsMgrBuilder::new(&mut be);
+            let sp = builder.add_sprite(&file_location);
+            (builder.build().expect("one sprite"), sp)
+        };
+        let mut q = CmdQueue::new(be.surface(), &ass);
+
+        q.rect(vec2(-10, 0.5), vec2(10, 10), 0);
+        q.sprite(vec2(0, 0), 0, sp);
+        q.set_origin(Origin::TopLeft);
+        q.rect(vec2(-10, 0.5), vec2(10, 10), 0);
+        q.sprite(vec2(0, 0), 0, sp);
+
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-5;
+
+        // same size in pixels, growing the other way
+        let rects = &q.batch().rects;
+        let RectLayout(a) = rects[0];
+        let RectLayout(b) = rects[1];
+        assert!(close(a[1], -1.025) && close(a[2], -1.0 + 0.5 / 300.0));
+        assert!(close(b[1], -1.025) && close(b[2], 1.0 - 0.5 / 300.0));
+        assert!(close(a[3], b[3]) && close(a[4], b[4]));
+        assert!(close(a[6], 10.0 / 300.0) && close(b[6], -10.0 / 300.0));
+
+        // sprites keep the size of their image, and are never upside down
+        let (w, h) = ass.get_sprite_size(sp).unwrap();
+        let (aw, ah) = ass.get_atlas_size();
+        let sprites = &q.batch().sprites;
+        let SpriteLayout(a) = sprites[0];
+        let SpriteLayout(b) = sprites[1];
+        assert!(close(a[3], w * aw / 400.0) && close(a[6], h * ah / 300.0));
+        assert!(close(b[3], a[3]) && close(b[6], a[6]));
+        assert!(close(a[2], -1.0) && close(b[2], 1.0 - a[6]));
+    }
+
+    #[test]
+    fn virtual_resolution() {
+        use super::resolution::VirtualResolution;
+        use super::maths::{Origin, Vec2};
+        use super::Contour;
+
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+        let mut q = CmdQueue::new(be.surface(), &ass);
+
+        let mut res = VirtualResolution::new(320, 180);
+        res.pixel_perfect = true;
+        q.set_virtual_resolution(Some(res));
+        assert_eq!(q.dimensions(), (320.0, 180.0));
+
+        // snapped to the virtual pixels
+        q.rect(vec2(159.6, 90.2), vec2(16, 9), 0);
+        let RectLayout(r) = q.batch().rects[0];
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-5;
+        assert!(close(r[1], 0.0) && close(r[2], 0.0));
+        assert!(close(r[3], 0.1) && close(r[6], 0.1));
+
+        // lines are as thick as the virtual pixels, borders too
+        q.line(vec2(0, 0), vec2(10, 0), 3, 0);
+        q.rect(vec2(0, 0), vec2(10, 10), 0).with_border(1);
+        let widths: Vec<u32> = q.batch().lines.keys().cloned().collect();
+        assert_eq!(widths, vec![2, 6]);
+
+        // the mouse in the middle of the window is in the middle of the virtual screen
+        let p = q.screen_to_world(Vec2::new(400.0, 300.0)).unwrap();
+        assert!(close(p.x, 160.0) && close(p.y, 90.0));
+        q.set_origin(Origin::TopLeft);
+        let p = q.screen_to_world(Vec2::new(80.0, 120.0)).unwrap();
+        assert!(close(p.x, 0.0) && close(p.y, 0.0));
+    }
+
+    #[test]
+    fn clip_stack() {
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+        let mut q = CmdQueue::new(be.surface(), &ass);
+
+        q.rect(vec2(0, 0), vec2(10, 10), 0);
+        q.push_clip(vec2(100, 100), vec2(200, 100));
+        q.rect(vec2(0, 0), vec2(10, 10), 0);
+        q.push_clip(vec2(250, 50), vec2(100, 100));
+        q.rect(vec2(0, 0), vec2(10, 10), 0);
+        q.pop_clip();
+        q.rect(vec2(0, 0), vec2(10, 10), 0);
+        q.pop_clip();
+        q.rect(vec2(0, 0), vec2(10, 10), 0);
+
+        let clips: Vec<_> = q.batches.iter().map(|b| b.state.clip).collect();
+        assert_eq!(clips,
+                   vec![None,
+                        Some(Viewport { left: 100, bottom: 100, width: 200, height: 100 }),
+                        Some(Viewport { left: 250, bottom: 100, width: 50, height: 50 })]);
+
+        // same clip, same batch
+        let rects: Vec<_> = q.batches.iter().map(|b| b.rects.len()).collect();
+        assert_eq!(rects, vec![2, 2, 1]);
+        assert_eq!(q.batch().rects.len(), 2);
+    }
+
+    #[test]
+    fn masks() {
+        use super::MaskStage::*;
+
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+        let mut q = CmdQueue::new(be.surface(), &ass);
+
+        q.begin_mask();
+        q.rect(vec2(0, 0), vec2(100, 100), 0);
+        q.end_mask();
+        q.rect(vec2(0, 0), vec2(10, 10), 0);
+        q.begin_mask();
+        q.rect(vec2(50, 50), vec2(100, 100), 0);
+        q.rect(vec2(60, 50), vec2(100, 100), 0);
+        q.end_mask();
+        q.rect(vec2(0, 0), vec2(10, 10), 0);
+        q.pop_mask();
+        q.rect(vec2(0, 0), vec2(10, 10), 0);
+        q.pop_mask();
+
+        // a sibling does not reuse the batches of the previous mask
+        q.begin_mask();
+        q.rect(vec2(0, 0), vec2(100, 100), 0);
+        q.end_mask();
+        q.pop_mask();
+        assert!(q.state.mask.is_none());
+
+        let stages: Vec<_> = q.batches
+            .iter()
+            .filter_map(|b| b.state.mask.map(|m| (m.id, m.depth, m.stage, b.rects.len())))
+            .collect();
+        assert_eq!(stages,
+                   vec![(0, 1, Write, 1),
+                        (0, 1, Draw, 2),
+                        (1, 2, Write, 2),
+                        (1, 2, Draw, 1),
+                        (1, 2, Erase, 2),
+                        (0, 1, Erase, 1),
+                        (2, 1, Write, 1),
+                        (2, 1, Draw, 0),
+                        (2, 1, Erase, 1)]);
+    }
+
+    #[test]
+    fn mask_with_states() {
+        use super::MaskStage::*;
+        use super::Blending;
+
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+        let mut q = CmdQueue::new(be.surface(), &ass);
+
+        // shapes with two blendings, and a clip pushed before the pop
+        q.begin_mask();
+        q.rect(vec2(0, 0), vec2(100, 100), 0);
+        q.rect(vec2(0, 0), vec2(50, 50), 0).with_blend(BlendMode::Additive);
+        q.end_mask();
+        q.push_clip(vec2(0, 0), vec2(10, 10));
+        q.rect(vec2(0, 0), vec2(10, 10), 0);
+        q.pop_mask();
+        q.pop_clip();
+
+        let erased: Vec<_> = q.batches
+            .iter()
+            .filter(|b| b.state.mask.map_or(false, |m| m.stage == Erase))
+            .map(|b| (b.state.blend, b.state.clip, b.rects.len()))
+            .collect();
+        assert_eq!(erased,
+                   vec![(BlendMode::Alpha, None, 1), (BlendMode::Additive, None, 1)]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn unbalanced_mask() {
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+        let mut q = CmdQueue::new(be.surface(), &ass);
+        q.begin_mask();
+        q.pop_mask();
+    }
+
+    #[test]
+    fn blend_modes() {
+        use super::Blending;
+        use super::Contour;
+
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+        let mut q = CmdQueue::new(be.surface(), &ass);
+
+        q.rect(vec2(0, 0), vec2(10, 10), 0);
+        q.rect(vec2(20, 0), vec2(10, 10), 0)
+            .with_blend(BlendMode::Multiply)
+            .with_color(0.0, 0.0, 0.0, 0.5)
+            .with_border(2);
+        q.line(vec2(0, 0), vec2(10, 10), 2, 0).with_blend(BlendMode::Additive);
+        q.push_clip(vec2(0, 0), vec2(100, 100));
+        q.rect(vec2(40, 0), vec2(10, 10), 0).with_blend(BlendMode::Multiply);
+        q.pop_clip();
+        q.rect(vec2(60, 0), vec2(10, 10), 0).with_blend(BlendMode::Multiply);
+        q.rect(vec2(80, 0), vec2(10, 10), 0);
+
+        let batches = &q.batches;
+        let summary: Vec<_> = batches.iter()
+            .map(|b| {
+                let lines = b.lines.get(&2).map_or(0, |l| l.len());
+                (b.state.blend, b.state.clip.is_some(), b.rects.len(), lines)
+            })
+            .collect();
+        assert_eq!(summary,
+                   vec![(BlendMode::Alpha, false, 2, 0),
+                        (BlendMode::Multiply, false, 2, 4),
+                        (BlendMode::Additive, false, 0, 1),
+                        (BlendMode::Alpha, true, 0, 0),
+                        (BlendMode::Multiply, true, 1, 0)]);
+
+        // the color went with the moved rect
+        let RectLayout(r) = batches[1].rects[0];
+        assert_eq!(r[10], 0.5);
+    }
+
+    #[test]
+    fn materials() {
+        use super::Shading;
+        use super::Blending;
+
+        let mut be = TestBE::default();
+        let (ass, dissolve, hue) = {
+            let mut mgr = AssetsMgrBuilder::new(&mut be);
+            let dissolve = Material::new("dissolve", "vec4 material(vec4 c, vec2 uv) { return c; }")
+                .with_uniform("amount", 0.5);
+            let hue = Material::new("hue", "vec4 material(vec4 c, vec2 uv) { return c.bgra; }");
+            let dissolve = mgr.add_material(&dissolve).expect("compiles");
+            let hue = mgr.add_material(&hue).expect("compiles");
+            (mgr.build().expect("no assets"), dissolve, hue)
+        };
+        assert_eq!(ass.get_material("dissolve"), Some(dissolve));
+        assert_eq!(ass.get_material("hue"), Some(hue));
+        assert_eq!(ass.get_material("scanlines"), None);
+
+        let mut q = CmdQueue::new(be.surface(), &ass);
+        q.rect(vec2(0, 0), vec2(10, 10), 0).with_material(dissolve);
+        q.rect(vec2(20, 0), vec2(10, 10), 0);
+        q.rect(vec2(40, 0), vec2(10, 10), 0).with_material(hue);
+        q.rect(vec2(60, 0), vec2(10, 10), 0)
+            .with_material(dissolve)
+            .with_color(1.0, 0.0, 0.0, 1.0);
+        q.rect(vec2(80, 0), vec2(10, 10), 0)
+            .with_material(dissolve)
+            .with_blend(BlendMode::Additive);
+        q.set_uniform(dissolve, "amount", 0.2);
+
+        let batches = &q.batches;
+        let summary: Vec<_> = batches.iter()
+            .map(|b| (b.state.material, b.state.blend, b.rects.len()))
+            .collect();
+        assert_eq!(summary,
+                   vec![(None, BlendMode::Alpha, 1),
+                        (Some(dissolve), BlendMode::Alpha, 2),
+                        (Some(hue), BlendMode::Alpha, 1),
+                        (Some(dissolve), BlendMode::Additive, 1)]);
+
+        // tunes keep working after moving
+        let RectLayout(r) = batches[1].rects[1];
+        assert_eq!(r[7], 1.0);
+        assert_eq!(r[8], 0.0);
+    }
+
+    #[test]
+    fn uniforms() {
+        use super::Shading;
+        use std::rc::Rc;
+        use std::cell::RefCell;
+
+        // the value of the uniform when each draw call is made
+        struct Recorder(Rc<RefCell<Vec<f32>>>, f32);
+        impl StreamLineBackendSurface for Recorder {
+            fn dimensions(&self) -> (f32, f32) {
+                (800.0, 600.0)
+            }
+            fn set_viewport(&mut self, _viewport: Option<Viewport>) {}
+            fn set_scissor(&mut self, _scissor: Option<Viewport>) {}
+            fn set_mask(&mut self, _mask: Option<Mask>) {}
+            fn set_blend(&mut self, _mode: BlendMode) {}
+            fn set_material(&mut self, _material: Option<MaterialId>) {}
+            fn set_uniform(&mut self, _material: MaterialId, _name: &str, value: f32) {
+                self.1 = value;
+            }
+            fn clear(&mut self, _color: &Color) {}
+            fn draw_sprites(&mut self, _sprites: &[SpriteLayout], _tex: u32) {}
+            fn draw_lines(&mut self, _lines: &[LineLayout], _width: u32) {}
+            fn draw_rects(&mut self, _rects: &[RectLayout]) {
+                self.0.borrow_mut().push(self.1);
+            }
+            fn draw_texts(&mut self, _rects: &[TextLayout]) {}
+            fn done(self) {}
+        }
+
+        let mut be = TestBE::default();
+        let (ass, dissolve) = {
+            let mut mgr = AssetsMgrBuilder::new(&mut be);
+            let dissolve = Material::new("dissolve", "vec4 material(vec4 c, vec2 uv) { return c; }")
+                .with_uniform("amount", 0.5);
+            let dissolve = mgr.add_material(&dissolve).expect("compiles");
+            (mgr.build().expect("no assets"), dissolve)
+        };
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut reusable = CmdQueue::reusable(&ass);
+        for _ in 0..2 {
+            let mut q = reusable.begin_frame(Recorder(log.clone(), 0.0));
+            q.rect(vec2(0, 0), vec2(10, 10), 0).with_material(dissolve);
+            q.set_uniform(dissolve, "amount", 0.2);
+            q.rect(vec2(20, 0), vec2(10, 10), 0).with_material(dissolve);
+            q.set_uniform(dissolve, "amount", 0.7);
+            q.rect(vec2(40, 0), vec2(10, 10), 0).with_material(dissolve);
+        }
+
+        // each rect with the value it was issued with, the next frame starts over
+        assert_eq!(*log.borrow(), vec![0.5, 0.2, 0.7, 0.5, 0.2, 0.7]);
+
+        // values no batch uses yet are changed in place
+        let mut q = CmdQueue::new(Recorder(log.clone(), 0.0), &ass);
+        q.set_uniform(dissolve, "amount", 0.1);
+        q.set_uniform(dissolve, "amount", 0.3);
+        q.rect(vec2(0, 0), vec2(10, 10), 0).with_material(dissolve);
+        q.set_uniform(dissolve, "amount", 0.4);
+        q.set_uniform(dissolve, "amount", 0.6);
+        assert_eq!(q.batches.uniforms.len(), 3);
+        assert_eq!(q.batches.uniforms[1], vec![("amount".to_string(), 0.3)]);
+        assert_eq!(q.batches.uniforms[2], vec![("amount".to_string(), 0.6)]);
+    }
+
+    #[test]
+    fn back_to_front() {
+        use super::{Blending, DrawOrder};
+        use std::rc::Rc;
+        use std::cell::RefCell;
+
+        // keeps the layers of every draw call
+        struct Recorder(Rc<RefCell<Vec<(&'static str, Vec<f32>)>>>);
+        impl StreamLineBackendSurface for Recorder {
+            fn dimensions(&self) -> (f32, f32) {
+                (800.0, 600.0)
+            }
+            fn set_viewport(&mut self, _viewport: Option<Viewport>) {}
+            fn set_scissor(&mut self, _scissor: Option<Viewport>) {}
+            fn set_mask(&mut self, _mask: Option<Mask>) {}
+            fn set_blend(&mut self, _mode: BlendMode) {}
+            fn set_material(&mut self, _material: Option<MaterialId>) {}
+            fn set_uniform(&mut self, _material: MaterialId, _name: &str, _value: f32) {}
+            fn clear(&mut self, _color: &Color) {}
+            fn draw_sprites(&mut self, _sprites: &[SpriteLayout], _tex: u32) {}
+            fn draw_lines(&mut self, lines: &[LineLayout], _w: u32) {
+                self.0.borrow_mut().push(("lines", lines.iter().map(|l| l.0[0]).collect()));
+            }
+            fn draw_rects(&mut self, rects: &[RectLayout]) {
+                self.0.borrow_mut().push(("rects", rects.iter().map(|l| l.0[0]).collect()));
+            }
+            fn draw_texts(&mut self, _rects: &[TextLayout]) {}
+            fn done(self) {}
+        }
+
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut q = CmdQueue::new(Recorder(log.clone()), &ass);
+        q.set_draw_order(DrawOrder::BackToFront);
+
+        q.rect(vec2(0, 0), vec2(10, 10), 2);
+        q.rect(vec2(0, 0), vec2(10, 10), 0);
+        q.line(vec2(0, 0), vec2(10, 10), 1, 1);
+        q.rect(vec2(0, 0), vec2(10, 10), 1).with_blend(BlendMode::Additive);
+        q.rect(vec2(0, 0), vec2(10, 10), 1).with_blend(BlendMode::Additive);
+        q.rect(vec2(0, 0), vec2(10, 10), 1);
+        q.done();
+
+        // neighbours from the same batch still share a draw call
+        let log = log.borrow();
+        let calls: Vec<_> = log.iter().map(|c| (c.0, c.1.len())).collect();
+        assert_eq!(calls, vec![("rects", 1), ("lines", 1), ("rects", 2), ("rects", 2)]);
+
+        // back to front, with the layers as issued
+        let layers: Vec<f32> = log.iter().flat_map(|c| c.1.clone()).collect();
+        assert_eq!(layers, vec![0.0, 1.0, 1.0, 1.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn layers() {
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+        let mut q = CmdQueue::new(be.surface(), &ass);
+
+        q.rect(vec2(0, 0), vec2(10, 10), 1000);
+        q.rect(vec2(0, 0), vec2(10, 10), -5);
+        q.line(vec2(0, 0), vec2(10, 10), 1, 1000);
+        q.rect(vec2(0, 0), vec2(10, 10), 1000);
+
+        // layers reach the backend as issued
+        let rects = &q.batch().rects;
+        let line = q.batch().lines[&1][0];
+        assert_eq!((rects[0].0[0], rects[1].0[0], line.0[0], rects[2].0[0]),
+                   (1000.0, -5.0, 1000.0, 1000.0));
+
+        // higher is nearer, and the depth of a layer does not depend on the others
+        let depth = super::layer_depth;
+        assert!(depth(-5.0) > depth(1000.0));
+        assert!(depth(0.0) > depth(1.0));
+        assert!(depth(-2048.0) < 1.0 && depth(2047.0) > 0.0);
+        assert_eq!(depth(1e9), depth(2047.0));
+        assert_eq!(depth(-1e9), depth(-2048.0));
+        assert_eq!(q.clamped_layers(), 0);
+    }
+
+    #[test]
+    fn layers_out_of_range() {
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+        let mut q = CmdQueue::new(be.surface(), &ass);
+
+        q.rect(vec2(0, 0), vec2(10, 10), 100000);
+        q.rect(vec2(0, 0), vec2(10, 10), -100000);
+        q.line(vec2(0, 0), vec2(10, 10), 1, 2047);
+        q.rect(vec2(0, 0), vec2(10, 10), -2048);
+
+        // they keep the layer they were issued with, but share a depth with
+        // the ends, so the queue counts them
+        let depth = super::layer_depth;
+        assert_eq!(q.batch().rects[0].0[0], 100000.0);
+        assert_eq!(depth(100000.0), depth(2047.0));
+        assert_eq!(depth(-100000.0), depth(-2048.0));
+        assert_eq!(q.clamped_layers(), 2);
+
+        assert!(super::layer_in_range(2047) && super::layer_in_range(-2048));
+        assert!(!super::layer_in_range(2048) && !super::layer_in_range(-2049));
+    }
+
+    #[test]
+    fn render_target() {
+        use super::Blending;
+
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+        let target = be.add_target(200, 100).expect("target");
+
+        // fill it with its own queue
+        let mut inner = CmdQueue::new(be.target_surface(&target), &ass);
+        inner.rect(vec2(0, 0), vec2(200, 100), 0);
+        inner.done();
+
+        let mut q = CmdQueue::new(be.surface(), &ass);
+        q.target_sprite(vec2(400, 300), 0, &target).with_blend(BlendMode::Premultiplied);
+        q.rect(vec2(0, 0), vec2(10, 10), 0);
+
+        let batches = &q.batches;
+        assert_eq!(batches.len(), 3);
+        assert_eq!(batches[0].rects.len(), 1);
+        assert_eq!(batches[1].sprites.len(), 0);
+        assert_eq!(batches[2].state.texture, Some(target.texture));
+        assert_eq!(batches[2].state.blend, BlendMode::Premultiplied);
+
+        // whole texture, at its size
+        let SpriteLayout(s) = batches[2].sprites[0];
+        assert_eq!(&s[1..], &[0.0, 0.0, 0.5, 0.0, 0.0, 1.0 / 3.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn reusable() {
+        use super::Blending;
+        use super::DrawOrder;
+
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+
+        let issue = |q: &mut CmdQueue<TestBESurface>| {
+            q.set_draw_order(DrawOrder::BackToFront);
+            for i in 0..100 {
+                q.rect(vec2(i, 0), vec2(10, 10), i);
+            }
+            q.line(vec2(0, 0), vec2(10, 10), 3, 0).with_blend(BlendMode::Additive);
+        };
+        let summary = |q: &CmdQueue<TestBESurface>| {
+            q.batches
+                .iter()
+                .map(|b| (b.state.blend, b.rects.list.clone(), b.lines.get(&3).map(|l| l.list.clone())))
+                .collect::<Vec<_>>()
+        };
+
+        let mut fresh = CmdQueue::new(be.surface(), &ass);
+        issue(&mut fresh);
+        let expected = summary(&fresh);
+
+        let mut reusable = CmdQueue::reusable(&ass);
+        let rects = {
+            let mut q = reusable.begin_frame(be.surface());
+            issue(&mut q);
+            q.batches[0].rects.list.as_ptr()
+        };
+        // ended with the guard
+        assert!(reusable.queue.surface.is_none());
+
+        // same as a new queue, in the memory of the last frame
+        {
+            let mut q = reusable.begin_frame(be.surface());
+            assert_eq!(q.order, DrawOrder::Batched);
+            assert!(q.batches.len() == 1 && q.batches[0].rects.is_empty());
+            issue(&mut q);
+            assert!(summary(&q) == expected);
+            assert_eq!(q.batches[0].rects.list.as_ptr(), rects);
+        }
+
+        // a forgotten guard leaves its frame pending, it is issued and not dropped
+        let mut q = reusable.begin_frame(be.surface());
+        issue(&mut q);
+        ::std::mem::forget(q);
+        let q = reusable.begin_frame(be.surface());
+        assert!(q.batches.len() == 1 && q.batches[0].rects.is_empty());
+    }
+
+    #[test]
+    fn text_hash() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::Hasher;
+        use tools::LayoutHash;
+
+        fn do_hash(texts: &[TextLayout]) -> u64 {
+            let mut h = DefaultHasher::new();
+            for t in texts {
+                t.hash(&mut h);
+            }
+            h.finish()
+        }
+
+        let text = |s: &str| {
+            TextLayout {
+                layer: 0.0,
+                pos: (0.0, 0.0),
+                color: (1.0, 1.0, 1.0, 1.0),
+                font: 0,
+                text: s.to_string(),
+            }
+        };
+
+        assert_eq!(do_hash(&[text("ab"), text("c")]),
+                   do_hash(&[text("ab"), text("c")]));
+        assert!(do_hash(&[text("ab"), text("c")]) != do_hash(&[text("a"), text("bc")]));
+    }
+
     #[bench]
     fn bench_lines(b: &mut Bencher) {
         // get some dummy backend
-        let mut be = TestBE;
+        let mut be = TestBE::default();
 
         // phase 1, load assets
         let ass = AssetsMgrBuilder::new(&mut be)
@@ -459,7 +2242,7 @@ mod tests {
             .expect("no problem so far");
 
         b.iter(|| {
-            let surface = be.surface(0);
+            let surface = be.surface();
             let mut q = CmdQueue::new(surface, &ass);
             q.clear(&[0.0f32, 0.0, 0.0, 0.0]);
             for _ in 0..1000 {
@@ -470,13 +2253,56 @@ mod tests {
         });
     }
 
+    #[bench]
+    fn bench_lines_reusable(b: &mut Bencher) {
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be)
+            .build()
+            .expect("no problem so far");
+
+        // same as bench_lines, without allocating the lists every frame
+        let mut reusable = CmdQueue::reusable(&ass);
+        b.iter(|| {
+            let mut q = reusable.begin_frame(be.surface());
+            q.clear(&[0.0f32, 0.0, 0.0, 0.0]);
+            for _ in 0..1000 {
+                q.line(vec2(0, 0), vec2(1, 1), 1, 0)
+                    .with_color(1.0, 1.0, 0.0, 1.0);
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_rects(b: &mut Bencher) {
+        use super::Contour;
+
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be)
+            .build()
+            .expect("no problem so far");
+
+        // every tune, the border goes through the width map
+        b.iter(|| {
+            let surface = be.surface();
+            let mut q = CmdQueue::new(surface, &ass);
+            q.clear(&[0.0f32, 0.0, 0.0, 0.0]);
+            for _ in 0..1000 {
+                q.rect(vec2(0, 0), vec2(10, 10), 0)
+                    .with_color(1.0, 1.0, 0.0, 1.0)
+                    .with_border(1)
+                    .with_color(1.0, 0.0, 0.0, 1.0);
+            }
+            q.done();
+        });
+    }
+
     #[bench]
     fn bench_sprites(b: &mut Bencher) {
         use std::path::Path;
         use find_folder::Search;
 
         // get some dummy backend
-        let mut be = TestBE;
+        let mut be = TestBE::default();
 
         let mut file_location = Search::Parents(3)
             .for_folder("assets")
@@ -491,7 +2317,7 @@ mod tests {
         };
 
         b.iter(|| {
-            let surface = be.surface(0);
+            let surface = be.surface();
             let mut q = CmdQueue::new(surface, &ass);
             q.clear(&[0.0f32, 0.0, 0.0, 0.0]);
             for _ in 0..1000 {
@@ -0,0 +1,30 @@
+/// Materials replace the color of sprites and rects with the application's
+/// own fragment logic.
+///
+/// The fragment is a GLSL function, `vec4 material(vec4 color, vec2 coords)`.
+/// `color` is what would have been drawn without the material: the texel
+/// times the sprite color for sprites, the rect color for rects. `coords` are
+/// the texture coordinates for sprites, and go from 0 to 1 across the quad
+/// for rects. Every uniform is a float, declared by the backend with the given name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Material {
+    pub name: String,
+    pub fragment: String,
+    /// uniform names, with their value until the queue sets another one
+    pub uniforms: Vec<(String, f32)>,
+}
+
+impl Material {
+    pub fn new(name: &str, fragment: &str) -> Material {
+        Material {
+            name: name.to_string(),
+            fragment: fragment.to_string(),
+            uniforms: Vec::new(),
+        }
+    }
+
+    pub fn with_uniform(mut self, name: &str, value: f32) -> Material {
+        self.uniforms.push((name.to_string(), value));
+        self
+    }
+}
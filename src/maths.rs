@@ -1,14 +1,153 @@
 
 
 
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Vec2 {
     pub x: f32,
     pub y: f32,
 }
 
-pub fn vec2(x: u32, y: u32) -> Vec2 {
+impl Vec2 {
+    pub fn new(x: f32, y: f32) -> Vec2 {
+        Vec2 { x: x, y: y }
+    }
+}
+
+/// any numeric type will do, negative and fractional positions are fine
+pub fn vec2<X: AsF32, Y: AsF32>(x: X, y: Y) -> Vec2 {
     Vec2 {
-        x: x as f32,
-        y: y as f32,
+        x: x.as_f32(),
+        y: y.as_f32(),
+    }
+}
+
+/// the numbers vec2 takes, big integers lose precision
+pub trait AsF32 {
+    fn as_f32(self) -> f32;
+}
+
+macro_rules! as_f32 {
+    ($($t:ty),*) => {
+        $(impl AsF32 for $t {
+            fn as_f32(self) -> f32 {
+                self as f32
+            }
+        })*
+    }
+}
+
+as_f32!(f32, f64, i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+/// where the pixel (0, 0) is, and which way y grows
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Origin {
+    /// y grows upwards, like in OpenGL
+    BottomLeft,
+    /// y grows downwards, like in window and image coordinates
+    TopLeft,
+}
+
+/// affine 2D transformation, stored by columns:
+// | m[0] m[2] m[4] |
+// | m[1] m[3] m[5] |
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform(pub [f32; 6]);
+
+impl Transform {
+    pub fn identity() -> Transform {
+        Transform([1.0, 0.0, 0.0, 1.0, 0.0, 0.0])
+    }
+
+    pub fn translate(x: f32, y: f32) -> Transform {
+        Transform([1.0, 0.0, 0.0, 1.0, x, y])
+    }
+
+    /// counter clockwise rotation, in radians
+    pub fn rotate(angle: f32) -> Transform {
+        let (sin, cos) = angle.sin_cos();
+        Transform([cos, sin, -sin, cos, 0.0, 0.0])
+    }
+
+    pub fn scale(x: f32, y: f32) -> Transform {
+        Transform([x, 0.0, 0.0, y, 0.0, 0.0])
+    }
+
+    /// composes two transformations, `other` is applied first
+    pub fn then(&self, other: &Transform) -> Transform {
+        let &Transform(a) = self;
+        let &Transform(b) = other;
+        Transform([a[0] * b[0] + a[2] * b[1],
+                   a[1] * b[0] + a[3] * b[1],
+                   a[0] * b[2] + a[2] * b[3],
+                   a[1] * b[2] + a[3] * b[3],
+                   a[0] * b[4] + a[2] * b[5] + a[4],
+                   a[1] * b[4] + a[3] * b[5] + a[5]])
+    }
+
+    /// the transformation that undoes this one, None if it collapses the plane
+    pub fn inverse(&self) -> Option<Transform> {
+        let &Transform(m) = self;
+        let det = m[0] * m[3] - m[1] * m[2];
+        if det == 0.0 {
+            return None;
+        }
+        let (a, b, c, d) = (m[3] / det, -m[1] / det, -m[2] / det, m[0] / det);
+        Some(Transform([a, b, c, d, -(a * m[4] + c * m[5]), -(b * m[4] + d * m[5])]))
+    }
+
+    /// transforms a position
+    pub fn apply(&self, p: Vec2) -> Vec2 {
+        let &Transform(m) = self;
+        Vec2 {
+            x: m[0] * p.x + m[2] * p.y + m[4],
+            y: m[1] * p.x + m[3] * p.y + m[5],
+        }
+    }
+
+    /// transforms a direction, translation does not apply
+    pub fn apply_vector(&self, v: Vec2) -> Vec2 {
+        let &Transform(m) = self;
+        Vec2 {
+            x: m[0] * v.x + m[2] * v.y,
+            y: m[1] * v.x + m[3] * v.y,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn close(a: Vec2, b: Vec2) -> bool {
+        (a.x - b.x).abs() < 1e-4 && (a.y - b.y).abs() < 1e-4
+    }
+
+    #[test]
+    fn transforms() {
+        let p = vec2(10, 0);
+
+        assert_eq!(Transform::identity().apply(p), p);
+        assert_eq!(Transform::translate(1.0, 2.0).apply(p), Vec2 { x: 11.0, y: 2.0 });
+        assert_eq!(Transform::scale(2.0, 3.0).apply(vec2(1, 1)), Vec2 { x: 2.0, y: 3.0 });
+        assert!(close(Transform::rotate(PI / 2.0).apply(p), Vec2 { x: 0.0, y: 10.0 }));
+
+        // scale first, then move
+        let t = Transform::translate(5.0, 5.0).then(&Transform::scale(2.0, 2.0));
+        assert_eq!(t.apply(p), Vec2 { x: 25.0, y: 5.0 });
+        assert_eq!(t.apply_vector(p), Vec2 { x: 20.0, y: 0.0 });
+
+        let r = Transform::rotate(0.3).then(&t);
+        let back = r.inverse().expect("can be undone");
+        assert!(close(back.apply(r.apply(Vec2::new(-3.0, 7.5))), Vec2::new(-3.0, 7.5)));
+        assert!(Transform::scale(0.0, 1.0).inverse().is_none());
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(vec2(-3, 2.5), Vec2::new(-3.0, 2.5));
+        assert_eq!(vec2(10u32, 0u8), Vec2::new(10.0, 0.0));
+        assert_eq!(vec2(3usize, -4i64), Vec2::new(3.0, -4.0));
     }
 }
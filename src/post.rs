@@ -0,0 +1,261 @@
+//! Full screen passes applied in order to the whole frame, with cpu versions of
+//! them for backends without shaders.
+
+use image::Rgba;
+use image::RgbaImage;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PostEffect {
+    /// color grading, 0, 1 and 1 leave the frame untouched
+    Grade {
+        brightness: f32,
+        contrast: f32,
+        saturation: f32,
+    },
+    /// darkens the corners, from radius to radius + softness away from
+    /// the center, in fractions of the frame size
+    Vignette { radius: f32, softness: f32 },
+    /// gaussian blur, the taps are radius pixels apart
+    Blur { radius: u32 },
+    /// the colors over the threshold are blurred and added back
+    Bloom { threshold: f32, intensity: f32 },
+    /// curved screen and dark scanlines every other row
+    Crt { curvature: f32, scanlines: f32 },
+}
+
+/// weights of the blur taps, the center one first
+pub const BLUR_WEIGHTS: [f32; 5] = [0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216];
+
+/// how far apart the bloom taps are, in pixels
+pub const BLOOM_RADIUS: u32 = 2;
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+// colors in floats, rows from the bottom like the gpu sees them
+#[derive(Clone)]
+struct Buffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<[f32; 4]>,
+}
+
+impl Buffer {
+    fn from_image(img: &RgbaImage) -> Buffer {
+        let (width, height) = img.dimensions();
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let p = img.get_pixel(x, height - 1 - y).data;
+                pixels.push([p[0] as f32 / 255.0,
+                             p[1] as f32 / 255.0,
+                             p[2] as f32 / 255.0,
+                             p[3] as f32 / 255.0]);
+            }
+        }
+        Buffer {
+            width: width,
+            height: height,
+            pixels: pixels,
+        }
+    }
+
+    fn to_image(&self) -> RgbaImage {
+        let mut img = RgbaImage::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let p = self.get(x as i32, y as i32);
+                let c = |v: f32| (v.max(0.0).min(1.0) * 255.0).round() as u8;
+                img.put_pixel(x, self.height - 1 - y, Rgba([c(p[0]), c(p[1]), c(p[2]), c(p[3])]));
+            }
+        }
+        img
+    }
+
+    // clamped to the edges, like the gpu samplers
+    fn get(&self, x: i32, y: i32) -> [f32; 4] {
+        let x = x.max(0).min(self.width as i32 - 1) as u32;
+        let y = y.max(0).min(self.height as i32 - 1) as u32;
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    fn map<F: Fn(u32, u32, [f32; 4]) -> [f32; 4]>(&self, f: F) -> Buffer {
+        let mut out = self.clone();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                out.pixels[(y * self.width + x) as usize] = f(x, y, self.get(x as i32, y as i32));
+            }
+        }
+        out
+    }
+
+    // center of the pixel, from 0 to 1
+    fn uv(&self, x: u32, y: u32) -> (f32, f32) {
+        ((x as f32 + 0.5) / self.width as f32, (y as f32 + 0.5) / self.height as f32)
+    }
+
+    fn blur(&self, radius: u32, dir: (i32, i32)) -> Buffer {
+        let radius = radius as i32;
+        self.map(|x, y, _| {
+            let mut acc = [0.0f32; 4];
+            for (i, w) in BLUR_WEIGHTS.iter().enumerate() {
+                let o = i as i32 * radius;
+                let taps = if i == 0 { vec![0] } else { vec![o, -o] };
+                for o in taps {
+                    let p = self.get(x as i32 + o * dir.0, y as i32 + o * dir.1);
+                    for k in 0..4 {
+                        acc[k] += p[k] * w;
+                    }
+                }
+            }
+            acc
+        })
+    }
+}
+
+fn smoothstep(e0: f32, e1: f32, x: f32) -> f32 {
+    let t = ((x - e0) / (e1 - e0)).max(0.0).min(1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn pass(effect: &PostEffect, src: &Buffer) -> Buffer {
+    match *effect {
+        PostEffect::Grade { brightness, contrast, saturation } => {
+            src.map(|_, _, c| {
+                let mut rgb = [0.0f32; 3];
+                for k in 0..3 {
+                    rgb[k] = (c[k] - 0.5) * contrast + 0.5 + brightness;
+                }
+                let luma = rgb[0] * 0.2126 + rgb[1] * 0.7152 + rgb[2] * 0.0722;
+                [luma + (rgb[0] - luma) * saturation,
+                 luma + (rgb[1] - luma) * saturation,
+                 luma + (rgb[2] - luma) * saturation,
+                 c[3]]
+            })
+        }
+        PostEffect::Vignette { radius, softness } => {
+            src.map(|x, y, c| {
+                let (u, v) = src.uv(x, y);
+                let d = ((u - 0.5) * (u - 0.5) + (v - 0.5) * (v - 0.5)).sqrt();
+                let f = 1.0 - smoothstep(radius, radius + softness, d);
+                [c[0] * f, c[1] * f, c[2] * f, c[3]]
+            })
+        }
+        PostEffect::Blur { radius } => src.blur(radius, (1, 0)).blur(radius, (0, 1)),
+        PostEffect::Bloom { threshold, intensity } => {
+            let bright = src.map(|_, _, c| {
+                [(c[0] - threshold).max(0.0),
+                 (c[1] - threshold).max(0.0),
+                 (c[2] - threshold).max(0.0),
+                 0.0]
+            });
+            let bright = bright.blur(BLOOM_RADIUS, (1, 0)).blur(BLOOM_RADIUS, (0, 1));
+            src.map(|x, y, c| {
+                let b = bright.get(x as i32, y as i32);
+                [c[0] + b[0] * intensity, c[1] + b[1] * intensity, c[2] + b[2] * intensity, c[3]]
+            })
+        }
+        PostEffect::Crt { curvature, scanlines } => {
+            src.map(|x, y, _| {
+                let (u, v) = src.uv(x, y);
+                let (du, dv) = (u - 0.5, v - 0.5);
+                let k = 1.0 + curvature * (du * du + dv * dv);
+                let (u, v) = (du * k + 0.5, dv * k + 0.5);
+                if u < 0.0 || u > 1.0 || v < 0.0 || v > 1.0 {
+                    return [0.0, 0.0, 0.0, 1.0];
+                }
+                let c = src.get((u * src.width as f32) as i32, (v * src.height as f32) as i32);
+                let f = if y % 2 == 0 { 1.0 - scanlines } else { 1.0 };
+                [c[0] * f, c[1] * f, c[2] * f, c[3]]
+            })
+        }
+    }
+}
+
+/// runs the effects one after the other, on the cpu
+pub fn apply(effects: &[PostEffect], img: &RgbaImage) -> RgbaImage {
+    let mut buffer = Buffer::from_image(img);
+    for effect in effects {
+        buffer = pass(effect, &buffer);
+    }
+    buffer.to_image()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn flat(w: u32, h: u32, c: [u8; 4]) -> RgbaImage {
+        RgbaImage::from_pixel(w, h, Rgba(c))
+    }
+
+    #[test]
+    fn grade() {
+        let img = flat(4, 4, [100, 150, 200, 255]);
+
+        let same = apply(&[PostEffect::Grade {
+                               brightness: 0.0,
+                               contrast: 1.0,
+                               saturation: 1.0,
+                           }],
+                         &img);
+        assert_eq!(same, img);
+
+        let gray = apply(&[PostEffect::Grade {
+                               brightness: 0.0,
+                               contrast: 1.0,
+                               saturation: 0.0,
+                           }],
+                         &img);
+        let p = gray.get_pixel(0, 0).data;
+        assert_eq!(p[0], p[1]);
+        assert_eq!(p[1], p[2]);
+        assert_eq!(p[3], 255);
+    }
+
+    #[test]
+    fn vignette_and_blur() {
+        let img = flat(9, 9, [200, 200, 200, 255]);
+        let out = apply(&[PostEffect::Vignette {
+                              radius: 0.2,
+                              softness: 0.3,
+                          }],
+                        &img);
+        assert_eq!(out.get_pixel(4, 4).data, [200, 200, 200, 255]);
+        assert!(out.get_pixel(0, 0).data[0] < 50);
+
+        // a flat image stays flat, a dot spreads
+        assert_eq!(apply(&[PostEffect::Blur { radius: 1 }], &img), img);
+        let mut dot = flat(9, 9, [0, 0, 0, 255]);
+        dot.put_pixel(4, 4, Rgba([255, 255, 255, 255]));
+        let out = apply(&[PostEffect::Blur { radius: 1 }], &dot);
+        assert!(out.get_pixel(4, 4).data[0] < 255);
+        assert!(out.get_pixel(5, 4).data[0] > 0);
+        assert!(out.get_pixel(4, 3).data[0] > 0);
+    }
+
+    #[test]
+    fn bloom_and_crt() {
+        let mut img = flat(13, 13, [100, 100, 100, 255]);
+        img.put_pixel(6, 6, Rgba([255, 255, 255, 255]));
+        let out = apply(&[PostEffect::Bloom {
+                              threshold: 0.8,
+                              intensity: 1.0,
+                          }],
+                        &img);
+        // only the bright pixel glows, the taps are BLOOM_RADIUS apart
+        assert!(out.get_pixel(8, 6).data[0] > 100);
+        assert_eq!(out.get_pixel(0, 0).data[0], 100);
+
+        let img = flat(8, 8, [200, 200, 200, 255]);
+        let out = apply(&[PostEffect::Crt {
+                              curvature: 0.0,
+                              scanlines: 0.5,
+                          }],
+                        &img);
+        // rows counted from the bottom, the last one is even
+        assert_eq!(out.get_pixel(0, 7).data[0], 100);
+        assert_eq!(out.get_pixel(0, 6).data[0], 200);
+    }
+}
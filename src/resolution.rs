@@ -0,0 +1,129 @@
+//! Authoring at a fixed resolution: the virtual screen is scaled to fit the
+//! surface, keeping its aspect ratio, with bars around.
+
+use maths::Vec2;
+use Color;
+
+/// area of the surface, in pixels from its bottom left corner
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Viewport {
+    pub left: u32,
+    pub bottom: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Viewport {
+    /// the area covered by both, empty if they do not overlap
+    pub fn intersect(&self, other: &Viewport) -> Viewport {
+        let left = self.left.max(other.left);
+        let bottom = self.bottom.max(other.bottom);
+        let right = (self.left + self.width).min(other.left + other.width);
+        let top = (self.bottom + self.height).min(other.bottom + other.height);
+        Viewport {
+            left: left,
+            bottom: bottom,
+            width: right.saturating_sub(left),
+            height: top.saturating_sub(bottom),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VirtualResolution {
+    pub width: u32,
+    pub height: u32,
+    /// scale only by whole numbers, and snap positions to the virtual pixels
+    pub pixel_perfect: bool,
+    /// color of the letterbox or pillarbox bars
+    pub bars: Color,
+}
+
+impl VirtualResolution {
+    pub fn new(width: u32, height: u32) -> VirtualResolution {
+        VirtualResolution {
+            width: width,
+            height: height,
+            pixel_perfect: false,
+            bars: [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+
+    /// surface pixels per virtual pixel
+    pub fn scale(&self, dim: (f32, f32)) -> f32 {
+        let s = (dim.0 / self.width as f32).min(dim.1 / self.height as f32);
+        // a surface smaller than the virtual screen can not be pixel perfect
+        if self.pixel_perfect && s >= 1.0 {
+            s.floor()
+        } else {
+            s
+        }
+    }
+
+    /// where the virtual screen ends up in the surface, centered
+    pub fn viewport(&self, dim: (f32, f32)) -> Viewport {
+        let s = self.scale(dim);
+        let width = (self.width as f32 * s).round().min(dim.0);
+        let height = (self.height as f32 * s).round().min(dim.1);
+        Viewport {
+            left: ((dim.0 - width) / 2.0).floor() as u32,
+            bottom: ((dim.1 - height) / 2.0).floor() as u32,
+            width: width as u32,
+            height: height as u32,
+        }
+    }
+
+    /// surface pixels, from the bottom left, to virtual pixels. none when
+    /// nothing is visible, like in a minimised window
+    pub fn to_virtual(&self, p: Vec2, dim: (f32, f32)) -> Option<Vec2> {
+        let vp = self.viewport(dim);
+        if vp.width == 0 || vp.height == 0 {
+            return None;
+        }
+        Some(Vec2 {
+            x: (p.x - vp.left as f32) * self.width as f32 / vp.width as f32,
+            y: (p.y - vp.bottom as f32) * self.height as f32 / vp.height as f32,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn letterbox() {
+        let res = VirtualResolution::new(320, 180);
+
+        // same aspect ratio, no bars
+        assert_eq!(res.viewport((1280.0, 720.0)),
+                   Viewport { left: 0, bottom: 0, width: 1280, height: 720 });
+
+        // taller window, bars above and below
+        assert_eq!(res.viewport((640.0, 480.0)),
+                   Viewport { left: 0, bottom: 60, width: 640, height: 360 });
+
+        // wider window, bars on the sides
+        assert_eq!(res.viewport((1000.0, 360.0)),
+                   Viewport { left: 180, bottom: 0, width: 640, height: 360 });
+
+        assert_eq!(res.to_virtual(Vec2::new(320.0, 240.0), (640.0, 480.0)),
+                   Some(Vec2::new(160.0, 90.0)));
+        assert_eq!(res.to_virtual(Vec2::new(0.0, 0.0), (0.0, 0.0)), None);
+    }
+
+    #[test]
+    fn pixel_perfect() {
+        let mut res = VirtualResolution::new(320, 180);
+        assert_eq!(res.scale((800.0, 600.0)), 2.5);
+
+        res.pixel_perfect = true;
+        assert_eq!(res.scale((800.0, 600.0)), 2.0);
+        assert_eq!(res.viewport((800.0, 600.0)),
+                   Viewport { left: 80, bottom: 120, width: 640, height: 360 });
+
+        // too small, just fit it
+        assert_eq!(res.scale((160.0, 90.0)), 0.5);
+    }
+}
@@ -0,0 +1,367 @@
+//! A retained tree of primitives for scenes that barely change: nodes only
+//! generate again what changed and replay their command lists otherwise.
+
+use CmdQueue;
+use StreamLineBackendSurface;
+use SpriteId;
+use FontId;
+use Color;
+use Colorize;
+use command_list::CommandList;
+use maths::Vec2;
+use maths::Transform;
+
+/// a node of a scene. once the node is removed the id is never valid again,
+/// even when its slot is reused
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct NodeId {
+    index: usize,
+    generation: u32,
+}
+
+/// what a node draws, groups just hold children
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    Group,
+    Sprite(SpriteId),
+    Rect { size: Vec2, color: Color },
+    Line { to: Vec2, width: u32, color: Color },
+    Text { font: FontId, text: String },
+}
+
+struct Node {
+    shape: Shape,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    local: Transform,
+    world: Transform,
+    layer: i32,
+    visible: bool,
+    // the local transform changed since the last update
+    dirty: bool,
+    // the shape at the origin, with the layouts of the last frame
+    list: CommandList,
+}
+
+struct Slot {
+    // bumped every time the node in the slot is removed
+    generation: u32,
+    node: Option<Node>,
+}
+
+pub struct Scene {
+    // removed nodes leave a hole, reused by the next one added
+    nodes: Vec<Slot>,
+    free: Vec<usize>,
+    roots: Vec<NodeId>,
+}
+
+// the primitive of a shape, drawn at the origin of the node
+fn record(shape: &Shape, layer: i32) -> CommandList {
+    let mut list = CommandList::new();
+    let origin = Vec2::new(0.0, 0.0);
+    match *shape {
+        Shape::Group => {}
+        Shape::Sprite(sprite) => list.sprite(origin, layer, sprite),
+        Shape::Rect { size, color } => {
+            list.rect(origin, size, layer).with_color(color[0], color[1], color[2], color[3]);
+        }
+        Shape::Line { to, width, color } => {
+            list.line(origin, to, width, layer).with_color(color[0], color[1], color[2], color[3]);
+        }
+        Shape::Text { font, ref text } => list.text(origin, layer, font, text),
+    }
+    list
+}
+
+impl Scene {
+    pub fn new() -> Scene {
+        Scene {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            roots: Vec::new(),
+        }
+    }
+
+    /// the node is drawn after its siblings, and before its children. none
+    /// when the parent was removed
+    pub fn add(&mut self, parent: Option<NodeId>, shape: Shape, layer: i32) -> Option<NodeId> {
+        if let Some(parent) = parent {
+            if self.node(parent).is_none() {
+                return None;
+            }
+        }
+
+        let node = Node {
+            list: record(&shape, layer),
+            shape: shape,
+            parent: parent,
+            children: Vec::new(),
+            local: Transform::identity(),
+            world: Transform::identity(),
+            layer: layer,
+            visible: true,
+            dirty: true,
+        };
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.nodes.push(Slot {
+                    generation: 0,
+                    node: None,
+                });
+                self.nodes.len() - 1
+            }
+        };
+        let slot = &mut self.nodes[index];
+        slot.node = Some(node);
+        let id = NodeId {
+            index: index,
+            generation: slot.generation,
+        };
+
+        match parent.and_then(|parent| self.node_mut(parent)) {
+            Some(parent) => parent.children.push(id),
+            None => self.roots.push(id),
+        }
+        Some(id)
+    }
+
+    /// removes the node with all its children, false if it was removed already
+    pub fn remove(&mut self, id: NodeId) -> bool {
+        let parent = match self.node(id) {
+            Some(node) => node.parent,
+            None => return false,
+        };
+        match parent.and_then(|parent| self.node_mut(parent)) {
+            Some(parent) => parent.children.retain(|c| *c != id),
+            None => self.roots.retain(|c| *c != id),
+        }
+        self.release(id);
+        true
+    }
+
+    fn release(&mut self, id: NodeId) {
+        let node = {
+            let slot = &mut self.nodes[id.index];
+            slot.generation += 1;
+            slot.node.take()
+        };
+        self.free.push(id.index);
+        for child in node.map_or(Vec::new(), |n| n.children) {
+            self.release(child);
+        }
+    }
+
+    /// false if the node was removed, same for the other setters
+    pub fn set_transform(&mut self, id: NodeId, t: Transform) -> bool {
+        match self.node_mut(id) {
+            Some(node) => {
+                if node.local != t {
+                    node.local = t;
+                    node.dirty = true;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_shape(&mut self, id: NodeId, shape: Shape) -> bool {
+        match self.node_mut(id) {
+            Some(node) => {
+                if node.shape != shape {
+                    node.list = record(&shape, node.layer);
+                    node.shape = shape;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_layer(&mut self, id: NodeId, layer: i32) -> bool {
+        match self.node_mut(id) {
+            Some(node) => {
+                if node.layer != layer {
+                    node.list = record(&node.shape, layer);
+                    node.layer = layer;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// hidden nodes are not drawn, and neither are their children
+    pub fn set_visible(&mut self, id: NodeId, visible: bool) -> bool {
+        match self.node_mut(id) {
+            Some(node) => {
+                node.visible = visible;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn transform(&self, id: NodeId) -> Option<Transform> {
+        self.node(id).map(|node| node.local)
+    }
+
+    /// the transform from the node to the world, as of the last update
+    pub fn world_transform(&self, id: NodeId) -> Option<Transform> {
+        self.node(id).map(|node| node.world)
+    }
+
+    /// computes the world transforms of the nodes that moved, and of their
+    /// children, returns how many were computed
+    pub fn update(&mut self) -> usize {
+        let mut count = 0;
+        let mut stack: Vec<(NodeId, Transform, bool)> =
+            self.roots.iter().rev().map(|id| (*id, Transform::identity(), false)).collect();
+        while let Some((id, parent, moved)) = stack.pop() {
+            let node = self.node_mut(id).expect("children are removed with their parent");
+            let moved = moved || node.dirty;
+            if moved {
+                node.world = parent.then(&node.local);
+                node.dirty = false;
+                count += 1;
+            }
+            let world = node.world;
+            stack.extend(node.children.iter().rev().map(|c| (*c, world, moved)));
+        }
+        count
+    }
+
+    /// updates the scene and issues all the visible nodes into the queue
+    pub fn draw<S>(&mut self, q: &mut CmdQueue<S>)
+        where S: StreamLineBackendSurface
+    {
+        self.update();
+
+        let mut stack: Vec<NodeId> = self.roots.iter().rev().cloned().collect();
+        while let Some(id) = stack.pop() {
+            let node = self.node_mut(id).expect("children are removed with their parent");
+            if !node.visible {
+                continue;
+            }
+            if !node.list.is_empty() {
+                q.replay(&mut node.list, node.world, [1.0, 1.0, 1.0, 1.0]);
+            }
+            stack.extend(node.children.iter().rev());
+        }
+    }
+
+    fn node(&self, id: NodeId) -> Option<&Node> {
+        self.nodes
+            .get(id.index)
+            .and_then(|slot| if slot.generation == id.generation { slot.node.as_ref() } else { None })
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+        self.nodes
+            .get_mut(id.index)
+            .and_then(|slot| if slot.generation == id.generation { slot.node.as_mut() } else { None })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use maths::vec2;
+    use AssetsMgrBuilder;
+    use StreamLineBackend;
+    use RectLayout;
+    use tests::TestBE;
+
+    #[test]
+    fn dirty_subtrees() {
+        let mut scene = Scene::new();
+        let world = scene.add(None, Shape::Group, 0).unwrap();
+        let a = scene.add(Some(world), Shape::Group, 0).unwrap();
+        let b = scene.add(Some(world), Shape::Group, 0).unwrap();
+        let c = scene.add(Some(a), Shape::Group, 0).unwrap();
+
+        assert_eq!(scene.update(), 4);
+        assert_eq!(scene.update(), 0);
+
+        // only a and its child
+        scene.set_transform(a, Transform::translate(10.0, 0.0));
+        scene.set_transform(c, Transform::translate(0.0, 5.0));
+        assert_eq!(scene.update(), 2);
+        assert_eq!(scene.world_transform(c).unwrap().apply(vec2(0, 0)), vec2(10, 5));
+        assert_eq!(scene.world_transform(b).unwrap().apply(vec2(0, 0)), vec2(0, 0));
+
+        // setting the same transform changes nothing
+        scene.set_transform(a, Transform::translate(10.0, 0.0));
+        assert_eq!(scene.update(), 0);
+
+        scene.set_transform(world, Transform::translate(1.0, 1.0));
+        assert_eq!(scene.update(), 4);
+        assert_eq!(scene.world_transform(c).unwrap().apply(vec2(0, 0)), vec2(11, 6));
+
+        // removing reuses the slots, but not the ids
+        assert!(scene.remove(a));
+        let d = scene.add(None, Shape::Group, 0).unwrap();
+        assert!(d != a && d != c);
+        assert!(!scene.remove(a));
+        assert!(!scene.remove(c));
+        assert!(!scene.set_transform(c, Transform::translate(1.0, 0.0)));
+        assert_eq!(scene.world_transform(c), None);
+        assert_eq!(scene.add(Some(a), Shape::Group, 0), None);
+        assert!(scene.set_visible(d, false));
+        assert_eq!(scene.update(), 1);
+    }
+
+    #[test]
+    fn draw() {
+        let mut be = TestBE::default();
+        let ass = AssetsMgrBuilder::new(&mut be).build().expect("no assets");
+
+        let mut scene = Scene::new();
+        let group = scene.add(None, Shape::Group, 0).unwrap();
+        scene.set_transform(group, Transform::translate(100.0, 100.0));
+        let red = scene.add(Some(group),
+                            Shape::Rect {
+                                size: vec2(10, 10),
+                                color: [1.0, 0.0, 0.0, 1.0],
+                            },
+                            2)
+            .unwrap();
+        let hidden = scene.add(None, Shape::Group, 0).unwrap();
+        scene.add(Some(hidden),
+                  Shape::Rect {
+                      size: vec2(10, 10),
+                      color: [0.0, 1.0, 0.0, 1.0],
+                  },
+                  1);
+        scene.set_visible(hidden, false);
+
+        let mut frame = |scene: &mut Scene| {
+            let mut q = CmdQueue::new(be.surface(), &ass);
+            scene.draw(&mut q);
+            let rects: Vec<RectLayout> = q.batches[0].rects.iter().cloned().collect();
+            rects
+        };
+
+        let first = frame(&mut scene);
+        assert_eq!(first.len(), 1);
+        let RectLayout(r) = first[0];
+        assert_eq!((r[0], r[7]), (2.0, 1.0));
+        // 100 pixels from the left of a 800 pixels wide surface
+        assert_eq!(r[1], -0.75);
+
+        // a static scene generates the same layouts
+        assert!(frame(&mut scene) == first);
+
+        assert!(scene.set_layer(red, 3));
+        let RectLayout(r) = frame(&mut scene)[0];
+        assert_eq!(r[0], 3.0);
+
+        // moved, the node generates its layouts again
+        assert!(scene.set_transform(group, Transform::translate(200.0, 100.0)));
+        let RectLayout(r) = frame(&mut scene)[0];
+        assert_eq!(r[1], -0.5);
+    }
+}
@@ -95,6 +95,12 @@ pub trait LayoutHash {
     fn hash<H: Hasher>(&self, h: &mut H);
 }
 
+impl<'a, L: LayoutHash> LayoutHash for &'a L {
+    fn hash<H: Hasher>(&self, h: &mut H) {
+        L::hash(*self, h)
+    }
+}
+
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
@@ -0,0 +1,151 @@
+//! TrueType/OpenType fonts, parsed and rasterized in pure rust.
+
+use rusttype;
+use rusttype::{point, Scale};
+
+use std::io;
+use std::sync::Arc;
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// a rasterized glyph, with the metrics needed to place it in a line of text
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlyphBitmap {
+    pub width: u32,
+    pub height: u32,
+    /// pixels from the cursor to the left of the bitmap
+    pub left: i32,
+    /// pixels from the base line to the top of the bitmap
+    pub top: i32,
+    /// pixels to move the cursor after this glyph
+    pub advance: f32,
+    /// one byte per pixel, rows from top to bottom
+    pub coverage: Vec<u8>,
+}
+
+/// a font with scalable outlines, cheap to clone
+#[derive(Clone)]
+pub struct VectorFont {
+    font: Arc<rusttype::Font<'static>>,
+}
+
+impl VectorFont {
+    /// parses a font file
+    pub fn from_bytes(data: Vec<u8>) -> io::Result<VectorFont> {
+        let font = rusttype::Font::from_bytes(data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        Ok(VectorFont { font: Arc::new(font) })
+    }
+
+    /// whenever the font has an outline for this character
+    pub fn has_glyph(&self, c: char) -> bool {
+        self.font.glyph(c).id().0 != 0
+    }
+
+    /// renders a character at a given size in pixels,
+    /// None if the font does not have this character
+    pub fn rasterize(&self, c: char, size: f32) -> Option<GlyphBitmap> {
+        if !self.has_glyph(c) {
+            return None;
+        }
+
+        let glyph = self.font.glyph(c).scaled(Scale::uniform(size));
+        let advance = glyph.h_metrics().advance_width;
+        let glyph = glyph.positioned(point(0.0, 0.0));
+
+        // blank glyphs, like spaces, have no bounding box
+        let bbox = match glyph.pixel_bounding_box() {
+            Some(bbox) => bbox,
+            None => {
+                return Some(GlyphBitmap {
+                    width: 0,
+                    height: 0,
+                    left: 0,
+                    top: 0,
+                    advance: advance,
+                    coverage: Vec::new(),
+                })
+            }
+        };
+
+        let width = bbox.width() as u32;
+        let height = bbox.height() as u32;
+        let mut coverage = vec![0u8; (width * height) as usize];
+        glyph.draw(|x, y, v| {
+            coverage[(y * width + x) as usize] = (v * 255.0).round() as u8;
+        });
+
+        Some(GlyphBitmap {
+            width: width,
+            height: height,
+            left: bbox.min.x,
+            // y grows downwards in rusttype
+            top: -bbox.min.y,
+            advance: advance,
+            coverage: coverage,
+        })
+    }
+
+    /// distance between two lines of text at a given size in pixels
+    pub fn line_height(&self, size: f32) -> f32 {
+        let v = self.font.v_metrics(Scale::uniform(size));
+        v.ascent - v.descent + v.line_gap
+    }
+
+    /// extra advance between two consecutive characters, in pixels
+    pub fn kerning(&self, size: f32, first: char, second: char) -> f32 {
+        self.font.pair_kerning(Scale::uniform(size), first, second)
+    }
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    use std::fs;
+    use std::io::Read;
+
+    fn open_sans() -> VectorFont {
+        use find_folder::Search;
+        use std::path::Path;
+
+        let mut file_location = Search::Parents(3)
+            .for_folder("assets")
+            .expect("some assets folder must exist somewhere");
+        file_location.push(Path::new("OpenSans-Regular.ttf"));
+
+        let mut data = Vec::new();
+        fs::File::open(file_location).unwrap().read_to_end(&mut data).unwrap();
+        VectorFont::from_bytes(data).expect("a valid font")
+    }
+
+    #[test]
+    fn rasterize() {
+        let font = open_sans();
+
+        let m = font.rasterize('M', 24.0).expect("M is in the font");
+        assert!(m.width > 0 && m.height > 0);
+        assert_eq!(m.coverage.len(), (m.width * m.height) as usize);
+        // sits on the base line
+        assert!(m.top > 0 && m.top <= 24);
+        assert!(m.advance > 0.0);
+        assert!(m.coverage.iter().any(|v| *v == 255));
+
+        let space = font.rasterize(' ', 24.0).expect("space is in the font");
+        assert_eq!((space.width, space.height), (0, 0));
+        assert!(space.advance > 0.0);
+
+        assert!(font.rasterize('\u{10FFFF}', 24.0).is_none());
+        assert!(font.line_height(24.0) >= m.height as f32);
+    }
+
+    #[test]
+    fn not_a_font() {
+        assert!(VectorFont::from_bytes(b"not a font".to_vec()).is_err());
+    }
+}
@@ -48,4 +48,20 @@ impl<T> VbCache<T>
 
         self.cache.get(&hash).expect("we just added it")
     }
+
+    /// value cached for an input, if any
+    pub fn get<L>(&mut self, layout: &[L]) -> Option<&T>
+        where L: LayoutHash
+    {
+        self.cache.get(&do_hash(layout))
+    }
+
+    /// caches a value for an input, replacing the previous one
+    pub fn put<L>(&mut self, layout: &[L], value: T) -> &T
+        where L: LayoutHash
+    {
+        let hash = do_hash(layout);
+        self.cache.put(hash, value);
+        self.cache.get(&hash).expect("we just added it")
+    }
 }
@@ -4,14 +4,11 @@
 #[macro_use]
 extern crate glium;
 extern crate glutin;
-extern crate cgmath;
 extern crate streamline;
 extern crate image;
 extern crate find_folder;
 extern crate lru;
 extern crate seahash;
-extern crate libc;
-extern crate freetype_sys as freetype;
 
 #[cfg(feature="profile")]
 extern crate flame;
@@ -20,6 +17,7 @@ mod line;
 mod quad;
 mod text;
 mod cache;
+mod post;
 
 use streamline::StreamLineBackend;
 use streamline::StreamLineBackendSurface;
@@ -28,27 +26,42 @@ use streamline::LineLayout;
 use streamline::RectLayout;
 use streamline::TextLayout;
 use streamline::Color;
+use streamline::VectorFont;
 use streamline::tools::RcRef;
+use streamline::resolution::Viewport;
+use streamline::Mask;
+use streamline::MaskStage;
+use streamline::BlendMode;
+use streamline::RenderTarget;
+use streamline::Material;
+use streamline::MaterialId;
+use streamline::post::PostEffect;
 
 use line::LineDraw;
 use quad::QuadDraw;
 use text::TextDraw;
+use post::PostDraw;
 
 use glium::Surface;
+use glium::texture::Texture2d;
+use glium::framebuffer::DepthStencilRenderBuffer;
 use image::RgbaImage;
 
 use std::collections::BTreeMap as Map;
 use std::rc::Rc;
-use std::ops::Deref;
 
 pub struct GliumBackend<'a> {
     display: &'a glium::Display,
     dimensions: (f32, f32),
-    map: Rc<Map<u32, glium::texture::Texture2d>>,
+    // shared with the surfaces, targets can be added while they are alive
+    map: RcRef<Map<u32, Rc<Texture2d>>>,
+    // depth and stencil for each render target, by texture id
+    targets: Map<u32, Rc<DepthStencilRenderBuffer>>,
 
     line_draw: RcRef<LineDraw>,
     quad_draw: RcRef<QuadDraw>,
     text_draw: RcRef<TextDraw>,
+    post_draw: RcRef<PostDraw>,
 }
 
 
@@ -58,11 +71,13 @@ impl<'a> GliumBackend<'a> {
         GliumBackend {
             display: display,
             dimensions: (dim.0 as f32, dim.1 as f32),
-            map: Rc::new(Map::new()),
+            map: RcRef::new(Map::new()),
+            targets: Map::new(),
 
             line_draw: RcRef::new(LineDraw::new(display)),
             quad_draw: RcRef::new(QuadDraw::new(display)),
             text_draw: RcRef::new(TextDraw::new(display)),
+            post_draw: RcRef::new(PostDraw::new(display)),
         }
     }
 }
@@ -71,52 +86,138 @@ impl<'a> StreamLineBackend for GliumBackend<'a> {
     type Surface = GliumBackendSurface<glium::Display>;
     fn add_texture(&mut self, img: RgbaImage) -> u32 {
 
-        let id = self.map.len() as u32;
+        let id = self.map.get().len() as u32;
 
         let dim = img.dimensions();
         // no idea why, but if not reversed, it just crashes
         // let texture = glium::texture::RawImage2d::from_raw_rgb(img.into_raw(),dim);
         let texture = glium::texture::RawImage2d::from_raw_rgba_reversed(&img.into_raw(), dim);
-        let texture = glium::texture::Texture2d::new(self.display, texture).unwrap();
+        let texture = Texture2d::new(self.display, texture).unwrap();
 
-        Rc::get_mut(&mut self.map).expect("no other one should be here").insert(id, texture);
+        self.map.get_mut().insert(id, Rc::new(texture));
         id
     }
 
-    fn add_font<FIO: std::io::Read>(&mut self, font: FIO) -> u32{
-        return self.text_draw.get_mut().add_font(self.display, font);
+    fn add_font(&mut self, font: VectorFont) -> std::io::Result<u32> {
+        self.text_draw.get_mut().add_font(self.display, font)
     }
 
-    fn surface(&mut self, layers: u32) -> Self::Surface {
+    fn add_material(&mut self, material: &Material) -> std::io::Result<MaterialId> {
+        self.quad_draw.get_mut().add_material(self.display, material)
+    }
+
+    fn set_post_effects(&mut self, effects: &[PostEffect]) -> std::io::Result<()> {
+        let dim = self.display.get_framebuffer_dimensions();
+        self.post_draw.get_mut().set_effects(self.display, effects, dim)
+    }
 
+    fn surface(&mut self) -> Self::Surface {
         let dim = self.display.get_framebuffer_dimensions();
+        let frame = self.display.draw();
+        // with effects, the frame only gets the result of the passes
+        let target = match self.post_draw.get_mut().scene(self.display, dim) {
+            Some((texture, depth)) => Target::Post(frame, texture, depth),
+            None => Target::Window(frame),
+        };
+        self.new_surface(target, dim)
+    }
 
+    fn add_target(&mut self, width: u32, height: u32) -> std::io::Result<RenderTarget> {
+        let texture = Texture2d::empty(self.display, width, height).map_err(target_error)?;
+        let depth = DepthStencilRenderBuffer::new(self.display,
+                                                  glium::texture::DepthStencilFormat::I24I8,
+                                                  width,
+                                                  height)
+            .map_err(target_error)?;
+
+        let id = self.map.get().len() as u32;
+        self.map.get_mut().insert(id, Rc::new(texture));
+        self.targets.insert(id, Rc::new(depth));
+
+        Ok(RenderTarget {
+            texture: id,
+            width: width,
+            height: height,
+        })
+    }
+
+    fn target_surface(&mut self, target: &RenderTarget) -> Self::Surface {
+        let texture = self.map.get().get(&target.texture).expect("not a render target").clone();
+        let depth = self.targets.get(&target.texture).expect("not a render target").clone();
+        self.new_surface(Target::Texture(texture, depth), (target.width, target.height))
+    }
+}
+
+impl<'a> GliumBackend<'a> {
+    fn new_surface(&self, target: Target, dim: (u32, u32)) -> GliumBackendSurface<glium::Display> {
         GliumBackendSurface {
-            frame: self.display.draw(),
+            target: target,
             line_draw: self.line_draw.clone(),
             quad_draw: self.quad_draw.clone(),
             text_draw: self.text_draw.clone(),
+            post_draw: self.post_draw.clone(),
             dimensions: (dim.0 as f32, dim.1 as f32),
-            layers: layers,
+            params: glium::DrawParameters {
+                depth: glium::Depth {
+                    test: glium::DepthTest::IfLessOrEqual,
+                    write: true,
+                    ..Default::default()
+                },
+                blend: glium::Blend::alpha_blending(),
+                ..Default::default()
+            },
+            material: None,
+            premultiply: false,
             display: self.display.clone(),
             tex_map: self.map.clone(),
         }
     }
 }
 
+/// where a surface ends up, the window or a render target
+enum Target {
+    Window(glium::Frame),
+    Texture(Rc<Texture2d>, Rc<DepthStencilRenderBuffer>),
+    // the window, drawn first into the scene texture of the post effects
+    Post(glium::Frame, Rc<Texture2d>, Rc<DepthStencilRenderBuffer>),
+}
+
+/// runs the body with $frame bound to whatever the surface draws into,
+/// framebuffers are cheap to create so we do it for every call
+macro_rules! on_target {
+    ($surface:ident, $frame:ident => $body:expr) => {
+        match $surface.target {
+            Target::Window(ref mut $frame) => $body,
+            Target::Texture(ref texture, ref depth) |
+            Target::Post(_, ref texture, ref depth) => {
+                let mut fb = glium::framebuffer::SimpleFrameBuffer::with_depth_stencil_buffer(
+                    &$surface.display, &**texture, &**depth)
+                    .expect("could not bind render target");
+                let $frame = &mut fb;
+                $body
+            }
+        }
+    }
+}
+
 pub struct GliumBackendSurface<F>
     where F: glium::backend::Facade
 {
-    frame: glium::Frame,
+    target: Target,
     line_draw: RcRef<LineDraw>,
     quad_draw: RcRef<QuadDraw>,
     text_draw: RcRef<TextDraw>,
+    post_draw: RcRef<PostDraw>,
     dimensions: (f32, f32),
-    layers: u32,
+    // state shared by every draw call, viewport, scissor...
+    params: glium::DrawParameters<'static>,
+    material: Option<MaterialId>,
+    // the blending expects colors multiplied by their alpha
+    premultiply: bool,
     display: F,
     // TODO: find a more sophisticated way, maybe when we 
     // get lifetimes in associated types
-    tex_map: Rc<Map<u32, glium::texture::Texture2d>>,
+    tex_map: RcRef<Map<u32, Rc<Texture2d>>>,
 }
 
 impl<F> StreamLineBackendSurface for GliumBackendSurface<F>
@@ -125,32 +226,146 @@ impl<F> StreamLineBackendSurface for GliumBackendSurface<F>
 
     fn dimensions(&self) -> (f32, f32){ self.dimensions }
 
+    fn set_viewport(&mut self, viewport: Option<Viewport>) {
+        self.params.viewport = viewport.map(to_rect);
+    }
+
+    fn set_scissor(&mut self, scissor: Option<Viewport>) {
+        self.params.scissor = scissor.map(to_rect);
+    }
+
+    fn set_mask(&mut self, mask: Option<Mask>) {
+        use glium::StencilTest::*;
+        use glium::StencilOperation::*;
+
+        // every mask adds one to the stencil, so nested ones only cover their parent
+        let (test, reference, op, hidden) = match mask {
+            None => (AlwaysPass, 0, Keep, false),
+            Some(mask) => {
+                match mask.stage {
+                    MaskStage::Write => (IfEqual { mask: !0 }, mask.depth - 1, Increment, true),
+                    MaskStage::Draw => (IfEqual { mask: !0 }, mask.depth, Keep, false),
+                    MaskStage::Erase => (IfEqual { mask: !0 }, mask.depth, Decrement, true),
+                }
+            }
+        };
+
+        self.params.stencil = glium::draw_parameters::Stencil {
+            test_clockwise: test,
+            reference_value_clockwise: reference as i32,
+            depth_pass_operation_clockwise: op,
+            test_counter_clockwise: test,
+            reference_value_counter_clockwise: reference as i32,
+            depth_pass_operation_counter_clockwise: op,
+            ..Default::default()
+        };
+
+        // the shapes of a mask are not visible, and do not hide anything
+        self.params.color_mask = (!hidden, !hidden, !hidden, !hidden);
+        self.params.depth = glium::Depth {
+            test: if hidden {
+                glium::DepthTest::Overwrite
+            } else {
+                glium::DepthTest::IfLessOrEqual
+            },
+            write: !hidden,
+            ..Default::default()
+        };
+    }
+
+    fn set_blend(&mut self, mode: BlendMode) {
+        use glium::BlendingFunction::Addition;
+        use glium::LinearBlendingFactor::*;
+
+        // multiply and screen only work with the alpha in the colors, the
+        // shaders multiply them for us: what is transparent is left as it was
+        let (source, destination) = match mode {
+            BlendMode::Alpha => (SourceAlpha, OneMinusSourceAlpha),
+            BlendMode::Additive => (SourceAlpha, One),
+            BlendMode::Multiply => (DestinationColor, OneMinusSourceAlpha),
+            BlendMode::Screen => (One, OneMinusSourceColor),
+            BlendMode::Premultiplied => (One, OneMinusSourceAlpha),
+        };
+        self.premultiply = mode == BlendMode::Multiply || mode == BlendMode::Screen;
+        let function = Addition {
+            source: source,
+            destination: destination,
+        };
+        self.params.blend = glium::Blend {
+            color: function,
+            alpha: function,
+            constant_value: (0.0, 0.0, 0.0, 0.0),
+        };
+    }
+
+    fn set_material(&mut self, material: Option<MaterialId>) {
+        self.material = material;
+    }
+
+    fn set_uniform(&mut self, material: MaterialId, name: &str, value: f32) {
+        self.quad_draw.get_mut().set_uniform(material, name, value);
+    }
+
     fn clear(&mut self, color: &Color) {
-        self.frame.clear_color(color[0], color[1], color[2], color[3]);
-        self.frame.clear_depth(1.0f32);
+        let color = (color[0], color[1], color[2], color[3]);
+        let viewport = self.params.viewport;
+        on_target!(self, frame => {
+            frame.clear(viewport.as_ref(), Some(color), false, Some(1.0f32), Some(0))
+        });
     }
 
     fn draw_sprites(&mut self, sprites: &[SpriteLayout], tex: u32) {
-        if let Some(tex) = self.tex_map.deref().get(&tex) {
-            self.quad_draw.get_mut().draw_tex_quads(&self.display, &mut self.frame, sprites, tex, self.layers);
+        let tex = self.tex_map.get().get(&tex).cloned();
+        if let Some(tex) = tex {
+            on_target!(self, frame => {
+                self.quad_draw.get_mut().draw_tex_quads(&self.display, frame, sprites, &tex, self.material, self.premultiply, &self.params)
+            });
         }
     }
 
     fn draw_lines(&mut self, lines: &[LineLayout], width: u32) {
-        self.line_draw.get_mut().draw_lines(&self.display, &mut self.frame, lines, width, self.layers);
+        on_target!(self, frame => {
+            self.line_draw.get_mut().draw_lines(&self.display, frame, lines, width, self.premultiply, &self.params)
+        });
     }
 
     fn draw_rects(&mut self, rects: &[RectLayout]) {
-        self.quad_draw.get_mut().draw_color_quads(&self.display, &mut self.frame, rects, self.layers);
+        on_target!(self, frame => {
+            self.quad_draw.get_mut().draw_color_quads(&self.display, frame, rects, self.material, self.premultiply, &self.params)
+        });
     }
 
     fn draw_texts(&mut self, texts: &[TextLayout]){
-        self.text_draw.get_mut().draw_texts(&mut self.frame, texts, self.dimensions);
+        on_target!(self, frame => {
+            self.text_draw.get_mut().draw_texts(frame, texts, self.premultiply, &self.params)
+        });
     }
 
     #[cfg_attr(feature="profile", flame)]
     fn done(self) {
-        self.frame.finish().expect("could not finish frame");
+        // a render target is ready as soon as the calls are issued
+        match self.target {
+            Target::Window(frame) => frame.finish().expect("could not finish frame"),
+            Target::Post(mut frame, _, _) => {
+                self.post_draw.get().apply(&self.display, &mut frame);
+                frame.finish().expect("could not finish frame");
+            }
+            Target::Texture(..) => {}
+        }
+    }
+}
+
+// creating the buffers of a target fails when the driver is out of memory
+fn target_error<E: std::fmt::Debug>(e: E) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, format!("render target: {:?}", e))
+}
+
+fn to_rect(vp: Viewport) -> glium::Rect {
+    glium::Rect {
+        left: vp.left,
+        bottom: vp.bottom,
+        width: vp.width,
+        height: vp.height,
     }
 }
 
@@ -173,7 +388,7 @@ mod tests {
 
         let mut events_loop = glutin::EventsLoop::new();
         let window = glutin::WindowBuilder::new().with_dimensions(1024,1024);
-        let context = glutin::ContextBuilder::new().with_depth_buffer(24);
+        let context = glutin::ContextBuilder::new().with_depth_buffer(24).with_stencil_buffer(8);
         let display = glium::Display::new(window, context, &events_loop).unwrap();
 
         // our backend
@@ -184,7 +399,7 @@ mod tests {
         while !stop && countdown > 0 {
 
             // ~~~~~~~~~~ raw drawing ~~~~~~~~~~~~~~~~
-            let mut surface = be.surface(6);
+            let mut surface = be.surface();
             surface.clear(&[0.5f32, 0.4, 0.8, 1.0]);
             surface.draw_lines(&[LineLayout([0.0, 0.0, 0.8, 0.8, 0.8, 1.0, 0.0, 0.0, 1.0f32])], 1);
             surface.draw_lines(&[LineLayout([0.0, -0.5, 0.4, 0.4, 0.4, 1.0, 1.0, 0.0, 1.0f32])], 2);
@@ -231,10 +446,10 @@ mod tests {
         while !stop && countdown > 0 {
 
             // ~~~~~~~~~~ raw drawing ~~~~~~~~~~~~~~~~
-            let mut surface = be.surface(1);
+            let mut surface = be.surface();
             surface.clear(&[0.7f32, 0.8, 0.3, 1.0]);
-            surface.draw_sprites(&[SpriteLayout([0.0, 0.0, 0.0, h, w, x, y, w, h])], 0);
-            surface.draw_sprites(&[SpriteLayout([0.0, -0.5, -0.5, h*0.5, w*0.5, x, y, w, h])], 0);
+            surface.draw_sprites(&[SpriteLayout([0.0, 0.0, 0.0, w, 0.0, 0.0, h, x, y, w, h, 1.0, 1.0, 1.0, 1.0])], 0);
+            surface.draw_sprites(&[SpriteLayout([0.0, -0.5, -0.5, w*0.5, 0.0, 0.0, h*0.5, x, y, w, h, 1.0, 1.0, 1.0, 1.0])], 0);
             surface.done();
 
             // ~~~~~~~~~~~   event ~~~~~~~~~~~~~~~~~
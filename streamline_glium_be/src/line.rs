@@ -1,6 +1,7 @@
 use glium;
 use glium::Surface;
 use streamline::LineLayout;
+use streamline::layer_depth;
 use streamline::tools::RcRef;
 use cache::VbCache;
 
@@ -45,11 +46,15 @@ impl LineDraw {
             fragment: "
                 #version 140
 
+                uniform bool premultiply;
                 in vec4 vs_color;
                 out vec4 fs_color;
 
                 void main() {
                     fs_color = vs_color;
+                    if (premultiply) {
+                        fs_color.rgb *= fs_color.a;
+                    }
                 }
             ",
 		});
@@ -62,15 +67,14 @@ impl LineDraw {
     #[cfg_attr(feature="profile", flame)]
     fn create_vb<F>(&mut self,
                          display: &F,
-                         lines: &[LineLayout],
-                         layers: u32) -> glium::VertexBuffer<LineVertex>
+                         lines: &[LineLayout]) -> glium::VertexBuffer<LineVertex>
     where F: glium::backend::Facade{
         let mut v = Vec::with_capacity(lines.len()*2);
         for instance in lines.iter() {
 
             let &LineLayout(l) = instance;
 
-            let depth = 1.0 - (l[0] / layers as f32);
+            let depth = layer_depth(l[0]);
 
             let x1 = l[1];
             let y1 = l[2];
@@ -98,31 +102,27 @@ impl LineDraw {
     }
 
     #[cfg_attr(feature="profile", flame)]
-    pub fn draw_lines<F>(&mut self,
+    pub fn draw_lines<F, S>(&mut self,
                          display: &F,
-                         frame: &mut glium::Frame,
+                         frame: &mut S,
                          lines: &[LineLayout],
                          width: u32,
-                         layers: u32)
-        where F: glium::backend::Facade
+                         premultiply: bool,
+                         base: &glium::DrawParameters)
+        where F: glium::backend::Facade,
+              S: glium::Surface
     {
         // process lines vector, generate some kind of list, here is where the caching could come handy
         let mut cache_ptr = self.vb_cache.clone();
         let mut cache = cache_ptr.get_mut();
-        let vertex_buffer = cache.test(lines, || self.create_vb(display, lines, layers) );
+        let vertex_buffer = cache.test(lines, || self.create_vb(display, lines) );
 
-        // some opengl stuff, that we will use as we need
-        let uniforms = glium::uniforms::EmptyUniforms {};
+        let uniforms = uniform!(premultiply: premultiply);
 
         let params = glium::DrawParameters {
-            depth: glium::Depth {
-                test: glium::DepthTest::IfLessOrEqual,
-                write: true,
-                ..Default::default()
-            },
             polygon_mode: glium::PolygonMode::Line,
             line_width: Some(width as f32),
-            ..Default::default()
+            ..base.clone()
         };
 
         frame.draw(vertex_buffer,
@@ -0,0 +1,317 @@
+use glium;
+use glium::Surface;
+use glium::texture::Texture2d;
+use glium::framebuffer::DepthStencilRenderBuffer;
+use glium::uniforms::Sampler;
+use glium::uniforms::SamplerWrapFunction;
+use glium::uniforms::MagnifySamplerFilter;
+use glium::uniforms::MinifySamplerFilter;
+use glium::uniforms::Uniforms;
+use streamline::post::PostEffect;
+use streamline::post::BLUR_WEIGHTS;
+use streamline::post::BLOOM_RADIUS;
+
+use std::io;
+use std::fmt::Debug;
+use std::rc::Rc;
+
+#[derive(Debug, Copy, Clone)]
+struct PostVertex {
+    position: [f32; 2],
+}
+implement_vertex!(PostVertex, position);
+
+const VERTEX: &'static str = "
+    #version 140
+    in vec2 position;
+    out vec2 uv;
+    void main() {
+        uv = position * 0.5 + 0.5;
+        gl_Position = vec4(position, 0.0, 1.0);
+    }
+";
+
+// every pass reads the previous one from source, see streamline::post
+// for the same passes on the cpu
+const GRADE: &'static str = "
+    uniform float brightness;
+    uniform float contrast;
+    uniform float saturation;
+    void main() {
+        vec4 c = texture(source, uv);
+        vec3 rgb = (c.rgb - 0.5) * contrast + 0.5 + brightness;
+        float luma = dot(rgb, vec3(0.2126, 0.7152, 0.0722));
+        color = vec4(mix(vec3(luma), rgb, saturation), c.a);
+    }
+";
+
+const VIGNETTE: &'static str = "
+    uniform float radius;
+    uniform float softness;
+    void main() {
+        vec4 c = texture(source, uv);
+        float f = 1.0 - smoothstep(radius, radius + softness, length(uv - 0.5));
+        color = vec4(c.rgb * f, c.a);
+    }
+";
+
+// the weights are the ones of the cpu version
+fn blur_body() -> String {
+    let weights: Vec<String> = BLUR_WEIGHTS.iter().map(|w| format!("{:.7}", w)).collect();
+    format!("
+    uniform vec2 offset;
+    const float weights[5] = float[5]({});
+    void main() {{
+        vec4 acc = texture(source, uv) * weights[0];
+        for (int i = 1; i < 5; i++) {{
+            acc += texture(source, uv + offset * float(i)) * weights[i];
+            acc += texture(source, uv - offset * float(i)) * weights[i];
+        }}
+        color = acc;
+    }}
+", weights.join(", "))
+}
+
+const BRIGHT: &'static str = "
+    uniform float threshold;
+    void main() {
+        vec4 c = texture(source, uv);
+        color = vec4(max(c.rgb - threshold, 0.0), 0.0);
+    }
+";
+
+const COMBINE: &'static str = "
+    uniform sampler2D bloom;
+    uniform float intensity;
+    void main() {
+        vec4 c = texture(source, uv);
+        color = vec4(c.rgb + texture(bloom, uv).rgb * intensity, c.a);
+    }
+";
+
+const CRT: &'static str = "
+    uniform float curvature;
+    uniform float scanlines;
+    void main() {
+        vec2 d = uv - 0.5;
+        vec2 p = d * (1.0 + curvature * dot(d, d)) + 0.5;
+        if (p.x < 0.0 || p.x > 1.0 || p.y < 0.0 || p.y > 1.0) {
+            color = vec4(0.0, 0.0, 0.0, 1.0);
+            return;
+        }
+        vec4 c = texture(source, p);
+        float f = mod(floor(gl_FragCoord.y), 2.0) == 0.0 ? 1.0 - scanlines : 1.0;
+        color = vec4(c.rgb * f, c.a);
+    }
+";
+
+fn compile<F>(f: &F, body: &str) -> io::Result<glium::Program>
+    where F: glium::backend::Facade
+{
+    let fragment = format!("
+        #version 140
+        uniform sampler2D source;
+        in vec2 uv;
+        out vec4 color;
+        {}", body);
+    glium::Program::from_source(f, VERTEX, &fragment, None)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("post effects: {}", e)))
+}
+
+fn failed<E: Debug>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("post effects: {:?}", e))
+}
+
+fn sample(texture: &Texture2d) -> Sampler<Texture2d> {
+    texture.sampled()
+        .wrap_function(SamplerWrapFunction::Clamp)
+        .magnify_filter(MagnifySamplerFilter::Nearest)
+        .minify_filter(MinifySamplerFilter::Nearest)
+}
+
+// the scene is drawn in the first texture, the passes bounce between all three
+struct Textures {
+    dimensions: (u32, u32),
+    color: [Rc<Texture2d>; 3],
+    depth: Rc<DepthStencilRenderBuffer>,
+}
+
+// compiled the first time there are effects to run
+struct Programs {
+    grade: glium::Program,
+    vignette: glium::Program,
+    blur: glium::Program,
+    bright: glium::Program,
+    combine: glium::Program,
+    crt: glium::Program,
+}
+
+impl Programs {
+    fn new<F>(f: &F) -> io::Result<Programs>
+        where F: glium::backend::Facade
+    {
+        Ok(Programs {
+            grade: compile(f, GRADE)?,
+            vignette: compile(f, VIGNETTE)?,
+            blur: compile(f, &blur_body())?,
+            bright: compile(f, BRIGHT)?,
+            combine: compile(f, COMBINE)?,
+            crt: compile(f, CRT)?,
+        })
+    }
+}
+
+pub struct PostDraw {
+    effects: Vec<PostEffect>,
+    quad: glium::VertexBuffer<PostVertex>,
+    programs: Option<Programs>,
+    textures: Option<Textures>,
+}
+
+impl PostDraw {
+    pub fn new<F>(f: &F) -> PostDraw
+        where F: glium::backend::Facade
+    {
+        let quad = [PostVertex { position: [-1.0, -1.0] },
+                    PostVertex { position: [1.0, -1.0] },
+                    PostVertex { position: [-1.0, 1.0] },
+                    PostVertex { position: [1.0, 1.0] }];
+
+        PostDraw {
+            effects: Vec::new(),
+            quad: glium::VertexBuffer::new(f, &quad).expect("something bad happen when creating vertex buffer"),
+            programs: None,
+            textures: None,
+        }
+    }
+
+    /// the programs and the textures are made here, on error the effects
+    /// are left as they were
+    pub fn set_effects<F>(&mut self, f: &F, effects: &[PostEffect], dim: (u32, u32)) -> io::Result<()>
+        where F: glium::backend::Facade
+    {
+        if effects.is_empty() {
+            self.effects.clear();
+            self.textures = None;
+            return Ok(());
+        }
+
+        if self.programs.is_none() {
+            self.programs = Some(Programs::new(f)?);
+        }
+        self.resize(f, dim)?;
+        self.effects = effects.to_vec();
+        Ok(())
+    }
+
+    fn resize<F>(&mut self, f: &F, dim: (u32, u32)) -> io::Result<()>
+        where F: glium::backend::Facade
+    {
+        if self.textures.as_ref().map_or(false, |t| t.dimensions == dim) {
+            return Ok(());
+        }
+        let texture = || Texture2d::empty(f, dim.0, dim.1).map(Rc::new).map_err(failed);
+        let depth = DepthStencilRenderBuffer::new(f, glium::texture::DepthStencilFormat::I24I8, dim.0, dim.1)
+            .map_err(failed)?;
+        self.textures = Some(Textures {
+            dimensions: dim,
+            color: [texture()?, texture()?, texture()?],
+            depth: Rc::new(depth),
+        });
+        Ok(())
+    }
+
+    /// where the window surface draws, if there is anything to apply. when
+    /// the window was resized and the textures can not follow, the frame
+    /// is drawn without effects
+    pub fn scene<F>(&mut self, f: &F, dim: (u32, u32)) -> Option<(Rc<Texture2d>, Rc<DepthStencilRenderBuffer>)>
+        where F: glium::backend::Facade
+    {
+        if self.effects.is_empty() || self.resize(f, dim).is_err() {
+            return None;
+        }
+        self.textures.as_ref().map(|t| (t.color[0].clone(), t.depth.clone()))
+    }
+
+    fn pass<F, U>(&self, f: &F, program: &glium::Program, uniforms: &U, target: &Texture2d)
+        where F: glium::backend::Facade,
+              U: Uniforms
+    {
+        let mut fb = glium::framebuffer::SimpleFrameBuffer::new(f, target).expect("could not bind post texture");
+        fb.draw(&self.quad,
+                  &glium::index::NoIndices(glium::index::PrimitiveType::TriangleStrip),
+                  program,
+                  uniforms,
+                  &Default::default())
+            .expect("failed to draw post pass");
+    }
+
+    fn blur_passes<F>(&self, f: &F, blur: &glium::Program, radius: u32, src: &Texture2d, tmp: &Texture2d, dst: &Texture2d)
+        where F: glium::backend::Facade
+    {
+        let (w, h) = src.dimensions();
+        let r = radius as f32;
+        self.pass(f, blur, &uniform!(source: sample(src), offset: [r / w as f32, 0.0f32]), tmp);
+        self.pass(f, blur, &uniform!(source: sample(tmp), offset: [0.0f32, r / h as f32]), dst);
+    }
+
+    /// runs the effects on the scene, and copies the result to the frame
+    pub fn apply<F>(&self, f: &F, frame: &mut glium::Frame)
+        where F: glium::backend::Facade
+    {
+        let (textures, p) = match (&self.textures, &self.programs) {
+            (&Some(ref textures), &Some(ref programs)) => (textures, programs),
+            _ => return,
+        };
+        let t = &textures.color;
+
+        // index of the texture holding the last result
+        let mut s = 0;
+        for effect in &self.effects {
+            let (a, b) = ((s + 1) % 3, (s + 2) % 3);
+            match *effect {
+                PostEffect::Grade { brightness, contrast, saturation } => {
+                    self.pass(f, &p.grade, &uniform!(
+                        source: sample(&t[s]),
+                        brightness: brightness,
+                        contrast: contrast,
+                        saturation: saturation
+                    ), &t[a]);
+                    s = a;
+                }
+                PostEffect::Vignette { radius, softness } => {
+                    self.pass(f, &p.vignette, &uniform!(
+                        source: sample(&t[s]),
+                        radius: radius,
+                        softness: softness
+                    ), &t[a]);
+                    s = a;
+                }
+                PostEffect::Blur { radius } => {
+                    self.blur_passes(f, &p.blur, radius, &t[s], &t[a], &t[b]);
+                    s = b;
+                }
+                PostEffect::Bloom { threshold, intensity } => {
+                    self.pass(f, &p.bright, &uniform!(source: sample(&t[s]), threshold: threshold), &t[a]);
+                    self.blur_passes(f, &p.blur, BLOOM_RADIUS, &t[a], &t[b], &t[a]);
+                    self.pass(f, &p.combine, &uniform!(
+                        source: sample(&t[s]),
+                        bloom: sample(&t[a]),
+                        intensity: intensity
+                    ), &t[b]);
+                    s = b;
+                }
+                PostEffect::Crt { curvature, scanlines } => {
+                    self.pass(f, &p.crt, &uniform!(
+                        source: sample(&t[s]),
+                        curvature: curvature,
+                        scanlines: scanlines
+                    ), &t[a]);
+                    s = a;
+                }
+            }
+        }
+
+        t[s].as_surface().fill(&*frame, MagnifySamplerFilter::Nearest);
+    }
+}
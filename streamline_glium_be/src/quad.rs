@@ -2,31 +2,65 @@ use glium;
 use glium::Surface;
 use streamline::SpriteLayout;
 use streamline::RectLayout;
+use streamline::layer_depth;
 use streamline::tools::RcRef;
+use streamline::Material;
+use streamline::MaterialId;
 use cache::VbCache;
 
 use std::vec::Vec;
+use std::io;
+use std::collections::BTreeMap as Map;
 use glium::texture::Texture2d;
+use glium::uniforms::Uniforms;
+use glium::uniforms::UniformValue;
 
 #[derive(Debug, Copy, Clone)]
 pub struct TexVertex {
     position: [f32; 3],
     coords: [f32; 2],
+    // the texture is multiplied by it
+    color: [f32; 4],
 }
-implement_vertex!(TexVertex, position, coords);
+implement_vertex!(TexVertex, position, coords, color);
 
 #[derive(Debug, Copy, Clone)]
 pub struct ColorVertex {
     position: [f32; 3],
     color: [f32; 4],
+    // from 0 to 1 across the quad, only read by materials
+    coords: [f32; 2],
+}
+implement_vertex!(ColorVertex, position, color, coords);
+
+/// a material, compiled once for sprites and once for rects
+struct MaterialDraw {
+    tex_program: glium::Program,
+    color_program: glium::Program,
+    uniforms: Map<String, f32>,
+}
+
+/// the uniforms of the fixed program, followed by the material ones
+struct MaterialUniforms<'a, U> {
+    base: U,
+    values: &'a Map<String, f32>,
+}
+
+impl<'a, U: Uniforms> Uniforms for MaterialUniforms<'a, U> {
+    fn visit_values<'b, F: FnMut(&str, UniformValue<'b>)>(&'b self, mut output: F) {
+        self.base.visit_values(&mut output);
+        for (name, value) in self.values.iter() {
+            output(name, UniformValue::Float(*value));
+        }
+    }
 }
-implement_vertex!(ColorVertex, position, color);
 
 pub struct QuadDraw {
     tex_program: glium::Program,
     color_program: glium::Program,
     spr_cache: RcRef<VbCache<(glium::VertexBuffer<TexVertex>, glium::IndexBuffer<u32>)>>, 
     rec_cache: RcRef<VbCache<(glium::VertexBuffer<ColorVertex>, glium::IndexBuffer<u32>)>>, 
+    materials: Vec<MaterialDraw>,
 }
 
 
@@ -41,19 +75,31 @@ impl QuadDraw {
                 #version 410 core
                 in vec3 position;
                 in vec2 coords;
+                in vec4 color;
                 out vec2 vs_coords;
+                out vec4 vs_color;
                 void main() {
                     vs_coords = coords;
+                    vs_color = color;
                     gl_Position = vec4(position, 1.0);
                 }
             ",
             fragment: "
                 #version 410 core
                 uniform sampler2D atlas;
+                uniform float alpha_cut;
+                uniform bool premultiply;
                 in vec2  vs_coords;
+                in vec4  vs_color;
                 out vec4 fs_color;
                 void main() {
-                    fs_color = texture(atlas, (vs_coords.xy)); 
+                    fs_color = texture(atlas, (vs_coords.xy)) * vs_color; 
+                    if (fs_color.a < alpha_cut) {
+                        discard;
+                    }
+                    if (premultiply) {
+                        fs_color.rgb *= fs_color.a;
+                    }
                     //fs_color = texelFetch(atlas, ivec2(vs_coords.xy), 0); 
                 }
             ",
@@ -73,10 +119,14 @@ impl QuadDraw {
             ",
             fragment: "
                 #version 140
+                uniform bool premultiply;
                 in vec4  vs_color;
                 out vec4 fs_color;
                 void main() {
                     fs_color = vs_color;
+                    if (premultiply) {
+                        fs_color.rgb *= fs_color.a;
+                    }
                 }
             ",
 		});
@@ -87,13 +137,111 @@ impl QuadDraw {
 
             spr_cache: RcRef::new(VbCache::new()),
             rec_cache: RcRef::new(VbCache::new()),
+            materials: Vec::new(),
         }
     }
 
-    #[cfg_attr(feature="profile", flame)]
-    pub fn draw_tex_quads<F>(&mut self, display: &F, frame: &mut glium::Frame, quads: &[SpriteLayout], texture: &Texture2d, layers: u32) 
+    /// the fragment is wrapped with the inputs of each program, see streamline::Material
+    pub fn add_material<F>(&mut self, f: &F, material: &Material) -> io::Result<MaterialId>
         where F: glium::backend::Facade
     {
+        let decls: String = material.uniforms
+            .iter()
+            .map(|&(ref name, _)| format!("uniform float {};\n", name))
+            .collect();
+
+        let tex_fragment = format!("
+                #version 140
+                uniform sampler2D atlas;
+                uniform float alpha_cut;
+                uniform bool premultiply;
+                {}
+                in vec2 vs_coords;
+                in vec4 vs_color;
+                out vec4 fs_color;
+                {}
+                void main() {{
+                    fs_color = material(texture(atlas, vs_coords) * vs_color, vs_coords);
+                    if (fs_color.a < alpha_cut) {{
+                        discard;
+                    }}
+                    if (premultiply) {{
+                        fs_color.rgb *= fs_color.a;
+                    }}
+                }}
+            ", decls, material.fragment);
+
+        let color_fragment = format!("
+                #version 140
+                uniform bool premultiply;
+                {}
+                in vec4 vs_color;
+                in vec2 vs_coords;
+                out vec4 fs_color;
+                {}
+                void main() {{
+                    fs_color = material(vs_color, vs_coords);
+                    if (premultiply) {{
+                        fs_color.rgb *= fs_color.a;
+                    }}
+                }}
+            ", decls, material.fragment);
+
+        let compile = |vertex: &str, fragment: &str| {
+            glium::Program::from_source(f, vertex, fragment, None)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", material.name, e)))
+        };
+
+        let tex_program = compile("
+                #version 140
+                in vec3 position;
+                in vec2 coords;
+                in vec4 color;
+                out vec2 vs_coords;
+                out vec4 vs_color;
+                void main() {
+                    vs_coords = coords;
+                    vs_color = color;
+                    gl_Position = vec4(position, 1.0);
+                }
+            ", &tex_fragment)?;
+
+        let color_program = compile("
+                #version 140
+                in vec3 position;
+                in vec4 color;
+                in vec2 coords;
+                out vec4 vs_color;
+                out vec2 vs_coords;
+                void main() {
+                    vs_color = color;
+                    vs_coords = coords;
+                    gl_Position = vec4(position, 1.0);
+                }
+            ", &color_fragment)?;
+
+        let id = self.materials.len();
+        self.materials.push(MaterialDraw {
+            tex_program: tex_program,
+            color_program: color_program,
+            uniforms: material.uniforms.iter().cloned().collect(),
+        });
+        Ok(id as MaterialId)
+    }
+
+    pub fn set_uniform(&mut self, material: MaterialId, name: &str, value: f32) {
+        if let Some(material) = self.materials.get_mut(material as usize) {
+            if let Some(v) = material.uniforms.get_mut(name) {
+                *v = value;
+            }
+        }
+    }
+
+    #[cfg_attr(feature="profile", flame)]
+    pub fn draw_tex_quads<F, S>(&mut self, display: &F, frame: &mut S, quads: &[SpriteLayout], texture: &Texture2d, material: Option<MaterialId>, premultiply: bool, base: &glium::DrawParameters) 
+        where F: glium::backend::Facade,
+              S: glium::Surface
+    {
 
         // process lines vector, generate some kind of list, here is where the caching could come handy
         let mut cache_ptr = self.spr_cache.clone();
@@ -105,38 +253,44 @@ impl QuadDraw {
 
                 let &SpriteLayout(l) = instance;
 
-                let depth = 1.0 - (l[0] / layers as f32);
+                let depth = layer_depth(l[0]);
 
                 let x = l[1];
                 let y = l[2];
-                let w = l[3];
-                let h = l[4];
+                let (ux, uy) = (l[3], l[4]);
+                let (vx, vy) = (l[5], l[6]);
+
+                let t_x = l[7];
+                let t_y = l[8];
+                let t_w = l[9];
+                let t_h = l[10];
 
-                let t_x = l[5];
-                let t_y = l[6];
-                let t_w = l[7];
-                let t_h = l[8];
+                let color = [l[11], l[12], l[13], l[14]];
 
                 let a = v.len();
                 v.push(TexVertex{
                         position: [x, y, depth],
                         coords: [t_x, t_y-t_h],
+                        color: color,
                         });
                 let b = v.len();
                 v.push(TexVertex{
-                        position: [x+w, y, depth],
+                        position: [x+ux, y+uy, depth],
                         coords: [t_x + t_w, t_y-t_h],
+                        color: color,
                         });
                 let c = v.len();
                 v.push(TexVertex{
-                        position: [x, y+h, depth],
+                        position: [x+vx, y+vy, depth],
                         coords: [t_x, t_y],
+                        color: color,
                         });
 
                 let d = v.len();
                 v.push(TexVertex{
-                        position: [x+w, y+h, depth],
+                        position: [x+ux+vx, y+uy+vy, depth],
                         coords: [t_x + t_w, t_y],
+                        color: color,
                         });
 
                 i.push(a as u32);
@@ -153,32 +307,45 @@ impl QuadDraw {
              )
         });
 
+        // when nothing is drawn we are writing a mask, and only the
+        // visible part of the sprite belongs to it
+        let alpha_cut = if base.color_mask == (false, false, false, false) { 0.5f32 } else { 0.0 };
+
         // some opengl stuff, that we will use as we need
         let uniforms = uniform!(
-            atlas: texture
+            atlas: texture,
+            alpha_cut: alpha_cut,
+            premultiply: premultiply
         );
 
         let params = glium::DrawParameters {
-            depth: glium::Depth {
-                test: glium::DepthTest::IfLessOrEqual,
-                write: true,
-                ..Default::default()
-            },
             polygon_mode: glium::PolygonMode::Fill,
             //polygon_mode: glium::PolygonMode::Line,
             line_width: Some(5.0),
-            blend: glium::draw_parameters::Blend::alpha_blending(),
-            ..Default::default()
+            ..base.clone()
         };
 
-        frame.draw(vertex_buffer, index_buffer,
-        		//&glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList),
-         		&self.tex_program, &uniforms, &params).expect("failed to draw lines");
+        match material.and_then(|m| self.materials.get(m as usize)) {
+            Some(material) => {
+                let uniforms = MaterialUniforms {
+                    base: uniforms,
+                    values: &material.uniforms,
+                };
+                frame.draw(vertex_buffer, index_buffer,
+                           &material.tex_program, &uniforms, &params).expect("failed to draw material");
+            }
+            None => {
+                frame.draw(vertex_buffer, index_buffer,
+                        //&glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList),
+                        &self.tex_program, &uniforms, &params).expect("failed to draw lines");
+            }
+        }
     }
 
     #[cfg_attr(feature="profile", flame)]
-    pub fn draw_color_quads<F>(&mut self, display: &F, frame: &mut glium::Frame, quads: &[RectLayout], layers: u32) 
-    where F: glium::backend::Facade
+    pub fn draw_color_quads<F, S>(&mut self, display: &F, frame: &mut S, quads: &[RectLayout], material: Option<MaterialId>, premultiply: bool, base: &glium::DrawParameters) 
+    where F: glium::backend::Facade,
+          S: glium::Surface
     {
         // process lines vector, generate some kind of list, here is where the caching could come handy
         let mut cache_ptr = self.rec_cache.clone();
@@ -191,38 +358,42 @@ impl QuadDraw {
 
                 let &RectLayout(l) = instance;
 
-                let depth = 1.0 - (l[0] / layers as f32);
+                let depth = layer_depth(l[0]);
 
                 let x = l[1];
                 let y = l[2];
-                let h = l[3];
-                let w = l[4];
+                let (ux, uy) = (l[3], l[4]);
+                let (vx, vy) = (l[5], l[6]);
 
-                let r = l[5];
-                let g = l[6];
-                let b = l[7];
-                let a = l[8];
+                let r = l[7];
+                let g = l[8];
+                let b = l[9];
+                let a = l[10];
 
                 let p1 = v.len();
                 v.push(ColorVertex{
                         position: [x, y, depth],
                         color: [r,g,b,a],
+                        coords: [0.0, 0.0],
                         });
                 let p2 = v.len();
                 v.push(ColorVertex{
-                        position: [x+w, y, depth],
+                        position: [x+ux, y+uy, depth],
                         color: [r,g,b,a],
+                        coords: [1.0, 0.0],
                         });
                 let p3 = v.len();
                 v.push(ColorVertex{
-                        position: [x, y+h, depth],
+                        position: [x+vx, y+vy, depth],
                         color: [r,g,b,a],
+                        coords: [0.0, 1.0],
                         });
 
                 let p4 = v.len();
                 v.push(ColorVertex{
-                        position: [x+w, y+h, depth],
+                        position: [x+ux+vx, y+uy+vy, depth],
                         color: [r,g,b,a],
+                        coords: [1.0, 1.0],
                         });
 
                 i.push(p1 as u32);
@@ -239,23 +410,32 @@ impl QuadDraw {
              )
         });
 
-        // some opengl stuff, that we will use as we need
-        let uniforms = glium::uniforms::EmptyUniforms {};
+        let uniforms = uniform!(premultiply: premultiply);
 
         let params = glium::DrawParameters {
-            depth: glium::Depth {
-                test: glium::DepthTest::IfLessOrEqual,
-                write: true,
-                ..Default::default()
-            },
             polygon_mode: glium::PolygonMode::Fill,
-            ..Default::default()
+            ..base.clone()
         };
 
-        frame.draw(vertex_buffer, index_buffer,
-                  &self.color_program,
-                  &uniforms,
-                  &params)
-            .expect("failed to draw lines");
+        match material.and_then(|m| self.materials.get(m as usize)) {
+            Some(material) => {
+                let uniforms = MaterialUniforms {
+                    base: uniforms,
+                    values: &material.uniforms,
+                };
+                frame.draw(vertex_buffer, index_buffer,
+                          &material.color_program,
+                          &uniforms,
+                          &params)
+                    .expect("failed to draw material");
+            }
+            None => {
+                frame.draw(vertex_buffer, index_buffer,
+                          &self.color_program,
+                          &uniforms,
+                          &params)
+                    .expect("failed to draw lines");
+            }
+        }
     }
 }
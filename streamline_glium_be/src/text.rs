@@ -1,51 +1,71 @@
 mod glium_text{
 
 use glium;
-use glium::DrawParameters;
 use glium::backend::Context;
 use glium::backend::Facade;
-use freetype;
-use libc;
+
+use streamline::TextLayout;
+use streamline::layer_depth;
+use streamline::VectorFont;
+use streamline::GlyphBitmap;
 
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::default::Default;
-use std::io::Read;
-use std::ops::Deref;
 use std::rc::Rc;
 use std;
 
+/// width, in pixels, of the texture where glyphs are rasterized, and its initial height
+const ATLAS_SIZE: u32 = 1024;
+
+/// a margin around each character to prevent artifacts
+const MARGIN: u32 = 2;
 
 /// Texture which contains the characters of the font.
+///
+/// Glyphs are rasterized the first time they are requested into a fixed size atlas
+/// split in cells of the same size. When the atlas is full, the least recently used
+/// glyph gives its cell away, unless it is used by the batch being built; then the
+/// atlas grows instead.
 pub struct FontTexture {
+    context: Rc<Context>,
+    font: VectorFont,
+    font_size: f32,
+
     texture: glium::texture::Texture2d,
-    character_infos: Vec<(char, CharacterInfos)>,
+    height: u32,
+    em_pixels: f32,
+    cell_size: u32,
+    cells_per_row: u32,
+
+    glyphs: HashMap<char, Glyph>,
+    tick: u64,
+    // glyphs used at or after this tick belong to the batch being built
+    floor: u64,
 }
 
-/// Object that contains the elements shared by all `TextDisplay` objects.
+/// Object that contains the elements shared by all `TextBatch` objects.
 ///
-/// Required to create a `TextDisplay`.
+/// Required to create a `TextBatch`.
 pub struct TextSystem {
     context: Rc<Context>,
     program: glium::Program,
 }
 
-/// Object that will allow you to draw a text.
-pub struct TextDisplay<F> where F: Deref<Target=FontTexture> {
-    context: Rc<Context>,
-    texture: F,
-    vertex_buffer: Option<glium::VertexBuffer<VertexFormat>>,
-    index_buffer: Option<glium::IndexBuffer<u16>>,
-    total_text_width: f32,
-    is_empty: bool,
+/// Geometry of a list of texts, ready to be drawn.
+pub struct TextBatch {
+    buffers: Option<(glium::VertexBuffer<VertexFormat>, glium::IndexBuffer<u32>)>,
+    // glyphs used, with the tick they were written at
+    glyphs: Vec<(char, u64)>,
 }
 
 // structure containing informations about a character of a font
 #[derive(Copy, Clone, Debug)]
 struct CharacterInfos {
-    // coordinates of the character top-left hand corner on the font's texture
+    // coordinates of the character top-left hand corner on the font's texture, in pixels
     tex_coords: (f32, f32),
 
-    // width and height of character in texture units
+    // width and height of character in pixels of the texture
     tex_size: (f32, f32),
 
     // size of the character in EMs
@@ -61,123 +81,218 @@ struct CharacterInfos {
     right_padding: f32,
 }
 
-struct TextureData {
-    data: Vec<f32>,
-    width: u32,
-    height: u32,
-}
-
-impl<'a> glium::texture::Texture2dDataSource<'a> for &'a TextureData {
-    type Data = f32;
-
-    fn into_raw(self) -> glium::texture::RawImage2d<'a, f32> {
-        glium::texture::RawImage2d {
-            data: Cow::Borrowed(&self.data),
-            width: self.width,
-            height: self.height,
-            format: glium::texture::ClientFormat::F32,
-        }
-    }
+// a rasterized character living in one of the atlas cells
+#[derive(Copy, Clone, Debug)]
+struct Glyph {
+    cell: u32,
+    // tick when the glyph was written to its cell
+    since: u64,
+    last_use: u64,
+    infos: CharacterInfos,
 }
 
 #[derive(Copy, Clone)]
 struct VertexFormat {
-    position: [f32; 2],
+    position: [f32; 3],
     tex_coords: [f32; 2],
+    color: [f32; 4],
 }
 
-implement_vertex!(VertexFormat, position, tex_coords);
+implement_vertex!(VertexFormat, position, tex_coords, color);
 
 impl FontTexture {
     /// Creates a new texture representing a font stored in a `FontTexture`.
-    pub fn new<R, F>(facade: &F, font: R, font_size: u32)
-                     -> Result<FontTexture, ()> where R: Read, F: Facade
+    /// fails if the glyphs of this size do not fit in the atlas
+    pub fn new<F>(facade: &F, font: VectorFont, font_size: u32)
+                  -> std::io::Result<FontTexture> where F: Facade
     {
-        // building the freetype library
-        // FIXME: call FT_Done_Library
-        let library = unsafe {
-            // taken from https://github.com/PistonDevelopers/freetype-rs/blob/master/src/library.rs
-            extern "C" fn alloc_library(_memory: freetype::FT_Memory, size: libc::c_long) -> *mut libc::c_void {
-                unsafe {
-                    libc::malloc(size as libc::size_t)
-                }
-            }
-            extern "C" fn free_library(_memory: freetype::FT_Memory, block: *mut libc::c_void) {
-                unsafe {
-                    libc::free(block)
-                }
-            }
-            extern "C" fn realloc_library(_memory: freetype::FT_Memory,
-                                          _cur_size: libc::c_long,
-                                          new_size: libc::c_long,
-                                          block: *mut libc::c_void) -> *mut libc::c_void {
-                unsafe {
-                    libc::realloc(block, new_size as libc::size_t)
-                }
-            }
-            static mut MEMORY: freetype::FT_MemoryRec = freetype::FT_MemoryRec {
-                user: 0 as *mut libc::c_void,
-                alloc: alloc_library,
-                free: free_library,
-                realloc: realloc_library,
-            };
+        // all cells are big enough to hold a line of text of this size
+        let line_height = font.line_height(font_size as f32).ceil() as u32;
+        let cell_size = std::cmp::max(font_size, line_height) + MARGIN;
+        let cells_per_row = ATLAS_SIZE / cell_size;
+        if cells_per_row == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                                           "font size too big for the glyph atlas"));
+        }
 
-            let mut raw = ::std::ptr::null_mut();
-            if freetype::FT_New_Library(&mut MEMORY, &mut raw) != freetype::FT_Err_Ok {
-                return Err(());
-            }
-            freetype::FT_Add_Default_Modules(raw);
+        // nothing is rasterized yet, cells are filled on demand
+        let texture = atlas(facade, ATLAS_SIZE)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("{:?}", e)))?;
 
-            raw
+        // FIXME: this is hacky, the height of 'M' is our EM
+        let em_pixels = match font.rasterize('M', font_size as f32) {
+            Some(ref m) if m.height > 0 => m.height as f32,
+            _ => font_size as f32,
         };
 
-        // building the freetype face object
-        let font: Vec<u8> = font.bytes().map(|c| c.unwrap()).collect();
+        Ok(FontTexture {
+            context: facade.get_context().clone(),
+            font: font,
+            font_size: font_size as f32,
 
-        let face: freetype::FT_Face = unsafe {
-            let mut face = ::std::ptr::null_mut();
-            let err = freetype::FT_New_Memory_Face(library, font.as_ptr(),
-                                                   font.len() as freetype::FT_Long, 0, &mut face);
-            if err == freetype::FT_Err_Ok {
-                face
-            } else {
-                return Err(());
-            }
-        };
+            texture: texture,
+            height: ATLAS_SIZE,
+            em_pixels: em_pixels,
+            cell_size: cell_size,
+            cells_per_row: cells_per_row,
+
+            glyphs: HashMap::new(),
+            tick: 0,
+            floor: 1,
+        })
+    }
 
-        // computing the list of characters in the font
-        let characters_list = unsafe {
-            // TODO: unresolved symbol
-            /*if freetype::FT_Select_CharMap(face, freetype::FT_ENCODING_UNICODE) != 0 {
-                return Err(());
-            }*/
+    /// Starts the geometry of a new batch, glyphs requested from now on keep their
+    /// cells until the next batch starts
+    pub fn begin_batch(&mut self) {
+        self.floor = self.tick + 1;
+    }
 
-            let mut result = Vec::new();
+    /// Marks the glyphs of a batch as used. false when any of them lost its cell,
+    /// the geometry of the batch then points to someone else's glyph
+    pub fn keep(&mut self, batch: &TextBatch) -> bool {
+        self.tick += 1;
+        let tick = self.tick;
+        let glyphs = &mut self.glyphs;
+        batch.glyphs.iter().all(|&(character, since)| {
+            match glyphs.get_mut(&character) {
+                Some(ref mut glyph) if glyph.since == since => {
+                    glyph.last_use = tick;
+                    true
+                }
+                _ => false,
+            }
+        })
+    }
 
-            let mut g: freetype::FT_UInt = std::mem::uninitialized();
-            let mut c = freetype::FT_Get_First_Char(face, &mut g);
+    /// Kerning between two characters, in EMs
+    fn kerning(&self, first: char, second: char) -> f32 {
+        self.font.kerning(self.font_size, first, second) / self.em_pixels
+    }
 
-            while g != 0 {
-                result.push(std::mem::transmute(c as u32));     // TODO: better solution?
-                c = freetype::FT_Get_Next_Char(face, c, &mut g);
-            }
+    /// Returns the glyph of a character, rasterizing it if it is not in the atlas.
+    /// None when the font has no glyph for this character
+    fn character_infos(&mut self, character: char) -> Option<Glyph> {
+        self.tick += 1;
+        if let Some(glyph) = self.glyphs.get_mut(&character) {
+            glyph.last_use = self.tick;
+            return Some(*glyph);
+        }
 
-            result
+        let bitmap = match self.font.rasterize(character, self.font_size) {
+            Some(bitmap) => bitmap,
+            None => return None,
         };
 
-        // building the infos
-        let (texture_data, chr_infos) = unsafe {
-            build_font_image(face, characters_list, font_size)
+        let cell = self.claim_cell();
+        let infos = self.write_glyph(cell, &bitmap);
+        let glyph = Glyph {
+            cell: cell,
+            since: self.tick,
+            last_use: self.tick,
+            infos: infos,
         };
+        self.glyphs.insert(character, glyph);
+        Some(glyph)
+    }
 
-        // we load the texture in the display
-        let texture = glium::texture::Texture2d::new(facade, &texture_data).unwrap();
+    // finds a cell for a new glyph, evicting the least recently used one if no free
+    // cells are left. cells are only released to be reused on the spot, therefore
+    // the first glyphs.len() cells are always the ones in use
+    fn claim_cell(&mut self) -> u32 {
+        let total = self.cells_per_row * (self.height / self.cell_size);
+        if (self.glyphs.len() as u32) < total {
+            return self.glyphs.len() as u32;
+        }
 
-        Ok(FontTexture {
-            texture: texture,
-            character_infos: chr_infos,
-        })
+        // glyphs of the batch being built are not up for grabs
+        let floor = self.floor;
+        let victim = self.glyphs
+            .iter()
+            .filter(|&(_, glyph)| glyph.last_use < floor)
+            .min_by_key(|&(_, glyph)| glyph.last_use)
+            .map(|(c, glyph)| (*c, glyph.cell));
+
+        match victim {
+            Some((character, cell)) => {
+                self.glyphs.remove(&character);
+                cell
+            }
+            None => {
+                self.grow();
+                self.glyphs.len() as u32
+            }
+        }
     }
+
+    // doubles the height of the atlas. cells keep their place, so geometry built
+    // before stays valid, but the glyphs have to be written again
+    fn grow(&mut self) {
+        self.height *= 2;
+        self.texture = atlas(&self.context, self.height).expect("could not grow the glyph atlas");
+
+        let glyphs: Vec<(char, u32)> = self.glyphs.iter().map(|(c, glyph)| (*c, glyph.cell)).collect();
+        for (character, cell) in glyphs {
+            if let Some(bitmap) = self.font.rasterize(character, self.font_size) {
+                self.write_glyph(cell, &bitmap);
+            }
+        }
+    }
+
+    // copies a rasterized glyph into a cell of the atlas
+    #[cfg_attr(feature="profile", flame)]
+    fn write_glyph(&mut self, cell: u32, bitmap: &GlyphBitmap) -> CharacterInfos {
+
+        // glyphs bigger than the cell get cropped
+        let room = self.cell_size - MARGIN;
+        let width = std::cmp::min(bitmap.width, room);
+        let rows = std::cmp::min(bitmap.height, room);
+
+        // the whole cell is rewritten, so nothing from the previous owner remains
+        let mut data = vec![0u8; (self.cell_size * self.cell_size) as usize];
+        for y in 0..rows {
+            let source = (y * bitmap.width) as usize;
+            let start = ((y + MARGIN) * self.cell_size + MARGIN) as usize;
+            data[start..start + width as usize]
+                .copy_from_slice(&bitmap.coverage[source..source + width as usize]);
+        }
+
+        let left = (cell % self.cells_per_row) * self.cell_size;
+        let bottom = (cell / self.cells_per_row) * self.cell_size;
+        self.texture.write(glium::Rect {
+                               left: left,
+                               bottom: bottom,
+                               width: self.cell_size,
+                               height: self.cell_size,
+                           },
+                           glium::texture::RawImage2d {
+                               data: Cow::Owned(data),
+                               width: self.cell_size,
+                               height: self.cell_size,
+                               format: glium::texture::ClientFormat::U8,
+                           });
+
+        let em = self.em_pixels;
+
+        CharacterInfos {
+            tex_size: (width as f32, rows as f32),
+            tex_coords: ((left + MARGIN) as f32, (bottom + MARGIN) as f32),
+            size: (width as f32 / em, rows as f32 / em),
+            left_padding: bitmap.left as f32 / em,
+            right_padding: (bitmap.advance - bitmap.width as f32 - bitmap.left as f32) / em,
+            height_over_line: bitmap.top as f32 / em,
+        }
+    }
+}
+
+// an empty atlas of the given height
+fn atlas<F>(facade: &F, height: u32) -> Result<glium::texture::Texture2d, glium::texture::TextureCreationError>
+    where F: Facade
+{
+    glium::texture::Texture2d::empty_with_format(facade,
+                                                 glium::texture::UncompressedFloatFormat::U8,
+                                                 glium::texture::MipmapsOption::NoMipmap,
+                                                 ATLAS_SIZE, height)
 }
 
 /*impl glium::uniforms::AsUniformValue for FontTexture {
@@ -189,7 +304,7 @@ impl FontTexture {
 impl TextSystem {
 
     #[cfg_attr(feature="profile", flame)]
-    /// Builds a new text system that must be used to build `TextDisplay` objects.
+    /// Builds a new text system that must be used to build `TextBatch` objects.
     pub fn new<F>(facade: &F) -> TextSystem where F: Facade {
         TextSystem {
             context: facade.get_context().clone(),
@@ -198,27 +313,33 @@ impl TextSystem {
                     vertex: "
                         #version 140
 
-                        uniform mat4 matrix;
-                        in vec2 position;
+                        in vec3 position;
                         in vec2 tex_coords;
+                        in vec4 color;
+                        uniform vec2 atlas;
 
                         out vec2 v_tex_coords;
+                        out vec4 v_color;
 
                         void main() {
-                            gl_Position = matrix * vec4(position, 0.0, 1.0);
-                            v_tex_coords = tex_coords;
+                            gl_Position = vec4(position, 1.0);
+                            v_tex_coords = tex_coords / atlas;
+                            v_color = color;
                         }
                     ",
                     fragment: "
                         #version 140
                         in vec2 v_tex_coords;
+                        in vec4 v_color;
                         out vec4 f_color;
-                        uniform vec4 color;
                         uniform sampler2D tex;
+                        uniform bool premultiply;
                         void main() {
-                            vec4 c = vec4(color.rgb, color.a * texture(tex, v_tex_coords));
+                            vec4 c = vec4(v_color.rgb, v_color.a * texture(tex, v_tex_coords).r);
                             if (c.a <= 0.01) {
                                 discard;
+                            } else if (premultiply) {
+                                f_color = vec4(c.rgb * c.a, c.a);
                             } else {
                                 f_color = c;
                             }
@@ -230,28 +351,35 @@ impl TextSystem {
                     vertex: "
                         #version 110
 
-                        attribute vec2 position;
+                        attribute vec3 position;
                         attribute vec2 tex_coords;
+                        attribute vec4 color;
+                        uniform vec2 atlas;
                         varying vec2 v_tex_coords;
-                        uniform mat4 matrix;
+                        varying vec4 v_color;
 
                         void main() {
-                            gl_Position = matrix * vec4(position.x, position.y, 0.0, 1.0);
-                            v_tex_coords = tex_coords;
+                            gl_Position = vec4(position, 1.0);
+                            v_tex_coords = tex_coords / atlas;
+                            v_color = color;
                         }
                     ",
                     fragment: "
                         #version 110
 
                         varying vec2 v_tex_coords;
-                        uniform vec4 color;
+                        varying vec4 v_color;
                         uniform sampler2D tex;
+                        uniform bool premultiply;
 
                         void main() {
-                            gl_FragColor = vec4(color.rgb, color.a * texture2D(tex, v_tex_coords));
+                            gl_FragColor = vec4(v_color.rgb, v_color.a * texture2D(tex, v_tex_coords).r);
                             if (gl_FragColor.a <= 0.01) {
                                 discard;
                             }
+                            if (premultiply) {
+                                gl_FragColor.rgb *= gl_FragColor.a;
+                            }
                         }
                     "
                 },
@@ -261,340 +389,170 @@ impl TextSystem {
     }
 }
 
-#[cfg_attr(feature="profile", flame)]
-impl<F> TextDisplay<F> where F: Deref<Target=FontTexture> {
-    /// Builds a new text display that allows you to draw text.
-    pub fn new(system: &TextSystem, texture: F, text: &str, dim: (f32, f32)) -> TextDisplay<F> {
-        let mut text_display = TextDisplay {
-            context: system.context.clone(),
-            texture: texture,
-            vertex_buffer: None,
-            index_buffer: None,
-            total_text_width: 0.0,
-            is_empty: true,
-        };
-
-        text_display.set_text(text, dim);
-
-        text_display
-    }
-
-    /// Returns the width in GL units of the text.
-    pub fn get_width(&self) -> f32 {
-        self.total_text_width
-    }
-
-    /// Modifies the text on this display.
-    pub fn set_text(&mut self, text: &str, dim: (f32, f32)) {
+impl TextBatch {
+    /// Builds the geometry of a list of texts sharing the same font.
+    /// Position and color of each text are baked into the vertices, so the whole
+    /// list is drawn with a single call.
+    #[cfg_attr(feature="profile", flame)]
+    pub fn new(system: &TextSystem, texture: &mut FontTexture, texts: &[&TextLayout]) -> TextBatch {
 
         let scale = 0.10;
         let v_scale = scale;
 
-        self.is_empty = true;
-        self.total_text_width = 0.0;
-        self.vertex_buffer = None;
-        self.index_buffer = None;
+        // these arrays will contain the vertex buffer and index buffer data
+        let len = texts.iter().fold(0, |acc, text| acc + text.text.len());
+        texture.begin_batch();
+        let mut vertex_buffer_data = Vec::with_capacity(len * 4);
+        let mut index_buffer_data = Vec::with_capacity(len * 6);
+        let mut glyphs = Vec::new();
+
+        for text in texts.iter() {
+
+            let color = [text.color.0, text.color.1, text.color.2, text.color.3];
+            let (x, y) = text.pos;
+            let depth = layer_depth(text.layer);
+            let mut text_width = 0.0;
+            let mut prev = None;
+
+            // iterating over the characters of the string
+            for character in text.text.chars() {     // FIXME: wrong, but only thing stable
+                if let Some(prev) = prev {
+                    text_width += texture.kerning(prev, character) * scale;
+                }
+                prev = Some(character);
+
+                let infos = match texture.character_infos(character) {
+                    Some(glyph) => {
+                        glyphs.push((character, glyph.since));
+                        glyph.infos
+                    }
+                    None => continue        // character not found in the font, ignoring it
+                };
+
+                // adding the quad in the index buffer
+                {
+                    let first_vertex_offset = vertex_buffer_data.len() as u32;
+                    index_buffer_data.push(first_vertex_offset);
+                    index_buffer_data.push(first_vertex_offset + 1);
+                    index_buffer_data.push(first_vertex_offset + 2);
+                    index_buffer_data.push(first_vertex_offset + 2);
+                    index_buffer_data.push(first_vertex_offset + 1);
+                    index_buffer_data.push(first_vertex_offset + 3);
+                }
 
-        // returning if no text
-        if text.len() == 0 {
-            return;
+                //
+                text_width += infos.left_padding * scale;
+
+                // calculating coords
+                let left_coord = x + text_width;
+                let right_coord = left_coord + infos.size.0 * scale;
+                let top_coord = y + infos.height_over_line * v_scale;
+                let bottom_coord = top_coord - infos.size.1 * scale;
+
+                // top-left vertex
+                vertex_buffer_data.push(VertexFormat {
+                    position: [left_coord, top_coord, depth],
+                    tex_coords: [infos.tex_coords.0, infos.tex_coords.1],
+                    color: color,
+                });
+
+                // top-right vertex
+                vertex_buffer_data.push(VertexFormat {
+                    position: [right_coord, top_coord, depth],
+                    tex_coords: [infos.tex_coords.0 + infos.tex_size.0, infos.tex_coords.1],
+                    color: color,
+                });
+
+                // bottom-left vertex
+                vertex_buffer_data.push(VertexFormat {
+                    position: [left_coord, bottom_coord, depth],
+                    tex_coords: [infos.tex_coords.0, infos.tex_coords.1 + infos.tex_size.1],
+                    color: color,
+                });
+
+                // bottom-right vertex
+                vertex_buffer_data.push(VertexFormat {
+                    position: [right_coord, bottom_coord, depth],
+                    tex_coords: [
+                        infos.tex_coords.0 + infos.tex_size.0,
+                        infos.tex_coords.1 + infos.tex_size.1
+                    ],
+                    color: color,
+                });
+
+                // going to next char
+                text_width = right_coord - x + infos.right_padding * scale;
+            }
         }
 
-        // these arrays will contain the vertex buffer and index buffer data
-        let mut vertex_buffer_data = Vec::with_capacity(text.len() * 4 * 4);
-        let mut index_buffer_data = Vec::with_capacity(text.len() * 6);
-
-        // iterating over the characters of the string
-        for character in text.chars() {     // FIXME: wrong, but only thing stable
-            let infos = match self.texture.character_infos
-                .iter().find(|&&(chr, _)| chr == character)
-            {
-                Some(infos) => infos,
-                None => continue        // character not found in the font, ignoring it
-            };
-            let infos = infos.1;
-
-            self.is_empty = false;
-
-            // adding the quad in the index buffer
-            {
-                let first_vertex_offset = vertex_buffer_data.len() as u16;
-                index_buffer_data.push(first_vertex_offset);
-                index_buffer_data.push(first_vertex_offset + 1);
-                index_buffer_data.push(first_vertex_offset + 2);
-                index_buffer_data.push(first_vertex_offset + 2);
-                index_buffer_data.push(first_vertex_offset + 1);
-                index_buffer_data.push(first_vertex_offset + 3);
-            }
+        glyphs.sort();
+        glyphs.dedup();
 
-            //
-            self.total_text_width += infos.left_padding * scale;
-
-            // calculating coords
-            let left_coord = self.total_text_width;
-            let right_coord = left_coord + infos.size.0 * scale;
-            let top_coord = infos.height_over_line * v_scale;
-            let bottom_coord = infos.height_over_line * v_scale - infos.size.1 * scale;
-
-            // top-left vertex
-            vertex_buffer_data.push(VertexFormat {
-                position: [left_coord, top_coord],
-                tex_coords: [infos.tex_coords.0, infos.tex_coords.1],
-            });
-
-            // top-right vertex
-            vertex_buffer_data.push(VertexFormat {
-                position: [right_coord, top_coord],
-                tex_coords: [infos.tex_coords.0 + infos.tex_size.0, infos.tex_coords.1],
-            });
-
-            // bottom-left vertex
-            vertex_buffer_data.push(VertexFormat {
-                position: [left_coord, bottom_coord],
-                tex_coords: [infos.tex_coords.0, infos.tex_coords.1 + infos.tex_size.1],
-            });
-
-            // bottom-right vertex
-            vertex_buffer_data.push(VertexFormat {
-                position: [right_coord, bottom_coord],
-                tex_coords: [
-                    infos.tex_coords.0 + infos.tex_size.0,
-                    infos.tex_coords.1 + infos.tex_size.1
-                ],
-            });
-
-            // going to next char
-            self.total_text_width = right_coord + infos.right_padding * scale;
+        if vertex_buffer_data.is_empty() {
+            return TextBatch {
+                buffers: None,
+                glyphs: glyphs,
+            };
         }
 
-        if !vertex_buffer_data.len() != 0 {
-            // building the vertex buffer
-            self.vertex_buffer = Some(glium::VertexBuffer::new(&self.context,
-                                                               &vertex_buffer_data).unwrap());
+        // building the vertex and index buffers
+        let vertex_buffer = glium::VertexBuffer::new(&system.context, &vertex_buffer_data).unwrap();
+        let index_buffer = glium::IndexBuffer::new(&system.context,
+                                                   glium::index::PrimitiveType::TrianglesList,
+                                                   &index_buffer_data).unwrap();
 
-            // building the index buffer
-            self.index_buffer = Some(glium::IndexBuffer::new(&self.context,
-                                     glium::index::PrimitiveType::TrianglesList,
-                                     &index_buffer_data).unwrap());
+        TextBatch {
+            buffers: Some((vertex_buffer, index_buffer)),
+            glyphs: glyphs,
         }
     }
 }
 
-pub fn draw<F, S: ?Sized, M>(text: &TextDisplay<F>, system: &TextSystem, target: &mut S,
-                             matrix: M, color: (f32, f32, f32, f32))
-                             where S: glium::Surface, M: Into<[[f32; 4]; 4]>,
-                                   F: Deref<Target=FontTexture>
+pub fn draw<S: ?Sized>(batch: &TextBatch, system: &TextSystem, texture: &FontTexture, target: &mut S,
+                       premultiply: bool, base: &glium::DrawParameters)
+                       where S: glium::Surface
 {
-    let matrix = matrix.into();
-
-    let &TextDisplay { ref vertex_buffer, ref index_buffer, ref texture, is_empty, .. } = text;
-    let color = [color.0, color.1, color.2, color.3];
-
     // returning if nothing to draw
-    if is_empty || vertex_buffer.is_none() || index_buffer.is_none() {
-        return;
-    }
-
-    let vertex_buffer = vertex_buffer.as_ref().unwrap();
-    let index_buffer = index_buffer.as_ref().unwrap();
+    let (vertex_buffer, index_buffer) = match batch.buffers {
+        Some((ref vertex_buffer, ref index_buffer)) => (vertex_buffer, index_buffer),
+        None => return,
+    };
 
     let uniforms = uniform! {
-        matrix: matrix,
-        color: color,
         tex: glium::uniforms::Sampler(&texture.texture, glium::uniforms::SamplerBehavior {
             magnify_filter: glium::uniforms::MagnifySamplerFilter::Linear,
             minify_filter: glium::uniforms::MinifySamplerFilter::Linear,
             .. Default::default()
-        })
-    };
-
-    let params = {
-        use glium::BlendingFunction::Addition;
-        use glium::LinearBlendingFactor::*;
-
-        let blending_function = Addition {
-            source: SourceAlpha,
-            destination: OneMinusSourceAlpha
-        };
-
-        let blend = glium::Blend {
-            color: blending_function,
-            alpha: blending_function,
-            constant_value: (1.0, 1.0, 1.0, 1.0),
-        };
-
-        DrawParameters {
-            blend: blend,
-            .. Default::default()
-        }
+        }),
+        atlas: [ATLAS_SIZE as f32, texture.height as f32],
+        premultiply: premultiply
     };
 
-    target.draw(vertex_buffer, index_buffer, &system.program, &uniforms, &params).unwrap();
-}
-
-#[cfg_attr(feature="profile", flame)]
-unsafe fn build_font_image(face: freetype::FT_Face, characters_list: Vec<char>, font_size: u32)
-                           -> (TextureData, Vec<(char, CharacterInfos)>)
-{
-    use std::iter;
-
-    // a margin around each character to prevent artifacts
-    const MARGIN: u32 = 2;
-
-    // setting the right pixel size
-    if freetype::FT_Set_Pixel_Sizes(face, font_size, font_size) != 0 {
-        panic!();
-    }
-
-    // this variable will store the texture data
-    // we set an arbitrary capacity that we think will match what we will need
-    let mut texture_data: Vec<f32> = Vec::with_capacity(characters_list.len() *
-                                                        font_size as usize * font_size as usize);
-
-    // the width is chosen more or less arbitrarily, because we can store everything as long as
-    //  the texture is at least as wide as the widest character
-    // we just try to estimate a width so that width ~= height
-    let texture_width = get_nearest_po2(std::cmp::max(font_size * 2 as u32,
-        ((((characters_list.len() as u32) * font_size * font_size) as f32).sqrt()) as u32));
-
-    // we store the position of the "cursor" in the destination texture
-    // this cursor points to the top-left pixel of the next character to write on the texture
-    let mut cursor_offset = (0u32, 0u32);
-
-    // number of rows to skip at next carriage return
-    let mut rows_to_skip = 0u32;
-
-    // now looping through the list of characters, filling the texture and returning the informations
-    let mut em_pixels = font_size as f32;
-    let mut characters_infos: Vec<(char, CharacterInfos)> = characters_list.into_iter().filter_map(|character| {
-        // loading wanted glyph in the font face
-        if freetype::FT_Load_Glyph(face, freetype::FT_Get_Char_Index(face, character as freetype::FT_ULong), freetype::FT_LOAD_RENDER) != 0 {
-            return None;
-        }
-        let bitmap = &(*(*face).glyph).bitmap;
-
-        // adding a left margin before our character to prevent artifacts
-        cursor_offset.0 += MARGIN;
-
-        // computing em_pixels
-        // FIXME: this is hacky
-        if character == 'M' {
-            em_pixels = bitmap.rows as f32;
-        }
-
-        // carriage return our cursor if we don't have enough room to write the next caracter
-        // we add a margin to prevent artifacts
-        if cursor_offset.0 + (bitmap.width as u32) + MARGIN >= texture_width {
-            assert!(bitmap.width as u32 <= texture_width);       // if this fails, we should increase texture_width
-            cursor_offset.0 = 0;
-            cursor_offset.1 += rows_to_skip;
-            rows_to_skip = 0;
-        }
-
-        // if the texture data buffer has not enough lines, adding some
-        if rows_to_skip < MARGIN + bitmap.rows as u32 {
-            let diff = MARGIN + (bitmap.rows as u32) - rows_to_skip;
-            rows_to_skip = MARGIN + bitmap.rows as u32;
-            texture_data.extend(iter::repeat(0.0).take((diff * texture_width) as usize));
-        }
-
-        // copying the data to the texture
-        let offset_x_before_copy = cursor_offset.0;
-        if bitmap.rows >= 1 {
-            let destination = &mut texture_data[(cursor_offset.0 + cursor_offset.1 * texture_width) as usize ..];
-            let source = std::mem::transmute(bitmap.buffer);
-            let source = std::slice::from_raw_parts(source, destination.len());
-
-            for y in 0 .. bitmap.rows as u32 {
-                let source = &source[(y * bitmap.width as u32) as usize ..];
-                let destination = &mut destination[(y * texture_width) as usize ..];
-
-                for x in 0 .. bitmap.width {
-                    // the values in source are bytes between 0 and 255, but we want floats between 0 and 1
-                    let val: u8 = *source.get(x as usize).unwrap();
-                    let val = (val as f32) / (std::u8::MAX as f32);
-                    let dest = destination.get_mut(x as usize).unwrap();
-                    *dest = val;
-                }
-            }
-
-            cursor_offset.0 += bitmap.width as u32;
-            debug_assert!(cursor_offset.0 <= texture_width);
-        }
-
-        // filling infos about that character
-        // tex_size and tex_coords are in pixels for the moment ; they will be divided
-        // by the texture dimensions later
-        let left_padding = (*(*face).glyph).bitmap_left;
-
-        Some((character, CharacterInfos {
-            tex_size: (bitmap.width as f32, bitmap.rows as f32),
-            tex_coords: (offset_x_before_copy as f32, cursor_offset.1 as f32),
-            size: (bitmap.width as f32, bitmap.rows as f32),
-            left_padding: left_padding as f32,
-            right_padding: ((*(*face).glyph).advance.x as i32 - bitmap.width * 64 - left_padding * 64) as f32 / 64.0,
-            height_over_line: (*(*face).glyph).bitmap_top as f32,
-        }))
-    }).collect();
-
-    // adding blank lines at the end until the height of the texture is a power of two
-    {
-        let current_height = texture_data.len() as u32 / texture_width;
-        let requested_height = get_nearest_po2(current_height);
-        texture_data.extend(iter::repeat(0.0).take((texture_width * (requested_height - current_height)) as usize));
-    }
-
-    // now our texture is finished
-    // we know its final dimensions, so we can divide all the pixels values into (0,1) range
-    assert!((texture_data.len() as u32 % texture_width) == 0);
-    let texture_height = (texture_data.len() as u32 / texture_width) as f32;
-    let float_texture_width = texture_width as f32;
-    for chr in characters_infos.iter_mut() {
-        chr.1.tex_size.0 /= float_texture_width;
-        chr.1.tex_size.1 /= texture_height;
-        chr.1.tex_coords.0 /= float_texture_width;
-        chr.1.tex_coords.1 /= texture_height;
-        chr.1.size.0 /= em_pixels;
-        chr.1.size.1 /= em_pixels;
-        chr.1.left_padding /= em_pixels;
-        chr.1.right_padding /= em_pixels;
-        chr.1.height_over_line /= em_pixels;
-    }
-
-    // returning
-    (TextureData {
-        data: texture_data,
-        width: texture_width,
-        height: texture_height as u32,
-    }, characters_infos)
-}
-
-/// Function that will calculate the nearest power of two.
-fn get_nearest_po2(mut x: u32) -> u32 {
-    assert!(x > 0);
-    x -= 1;
-    x = x | (x >> 1);
-    x = x | (x >> 2);
-    x = x | (x >> 4);
-    x = x | (x >> 8);
-    x = x | (x >> 16);
-    x + 1
+    // blending comes with the batch, like for every other primitive
+    target.draw(vertex_buffer, index_buffer, &system.program, &uniforms, base).unwrap();
 }
 
 }
 
 
-use cgmath;
 use glium;
 
-//use glium::Surface;
 use streamline::TextLayout;
+use streamline::VectorFont;
+use cache::VbCache;
 use std::io;
 use std::vec::Vec;
+use std::collections::BTreeMap as Map;
+
+// a font and the geometry generated with it in previous frames
+struct Font {
+    texture: glium_text::FontTexture,
+    cache: VbCache<glium_text::TextBatch>,
+}
 
 pub struct TextDraw{
     sys: glium_text::TextSystem,
-    fonts: Vec<glium_text::FontTexture>,
+    fonts: Vec<Font>,
 }
 
 impl TextDraw {
@@ -610,34 +568,48 @@ impl TextDraw {
         }
     }
 
-    pub fn add_font<F, FIO>(&mut self, f: &F, font: FIO) -> u32
-        where F: glium::backend::Facade,
-              FIO: io::Read
+    pub fn add_font<F>(&mut self, f: &F, font: VectorFont) -> io::Result<u32>
+        where F: glium::backend::Facade
     {
-        let font = glium_text::FontTexture::new(f, font, 24).unwrap();
+        let font = glium_text::FontTexture::new(f, font, 24)?;
         let i = self.fonts.len();
-        self.fonts.push(font);
-        i as u32
+        self.fonts.push(Font {
+            texture: font,
+            cache: VbCache::new(),
+        });
+        Ok(i as u32)
     }
 
+    /// draws all texts with one call per font, geometry is reused while the
+    /// texts of a font do not change from one frame to the next
     #[cfg_attr(feature="profile", flame)]
-    pub fn draw_texts(&self, frame: &mut glium::Frame, txts: &[TextLayout], dim: (f32, f32))
+    pub fn draw_texts<S: glium::Surface>(&mut self, frame: &mut S, txts: &[TextLayout], premultiply: bool, base: &glium::DrawParameters)
         {
-    
+
+        let mut per_font = Map::new();
         for entry in txts.iter(){
+            per_font.entry(entry.font).or_insert_with(Vec::new).push(entry);
+        }
 
-            let font = &self.fonts[entry.font as usize];
-            let pos = entry.pos;
+        for (font, entries) in per_font.iter(){
 
-            let text = glium_text::TextDisplay::new(&self.sys, font, entry.text.as_str(), dim);
+            let font = &mut self.fonts[*font as usize];
 
-            let t = cgmath::Matrix4::from_translation( cgmath::Vector3::new(pos.0, pos.1, 0.0));
+            // cached geometry is rebuilt only when one of its own glyphs was evicted
+            let current = match font.cache.get(entries.as_slice()) {
+                Some(batch) => font.texture.keep(batch),
+                None => false,
+            };
+
+            let sys = &self.sys;
+            let batch = if current {
+                font.cache.get(entries.as_slice()).expect("we just found it")
+            } else {
+                let batch = glium_text::TextBatch::new(sys, &mut font.texture, entries.as_slice());
+                font.cache.put(entries.as_slice(), batch)
+            };
 
-            let matrix:[[f32; 4]; 4] = (t).into();
-            glium_text::draw(&text,
-                             &self.sys, 
-                             frame,
-                             matrix, entry.color);
+            glium_text::draw(batch, sys, &font.texture, frame, premultiply, base);
         }
     }
 }